@@ -1,17 +1,36 @@
+use crate::config::Config;
 use anyhow::{bail, Context, Result};
 use clap::{Args, Parser, Subcommand};
 use clap_cargo_extra::ClapCargo;
 use heck::ToKebabCase;
-use regex::Regex;
+use similar::TextDiff;
 use std::{
-    collections::HashMap,
-    // fmt::Write,
-    fs::{read, OpenOptions},
+    ffi::OsStr,
+    fmt::Display,
+    fs::{metadata, read, read_to_string, OpenOptions},
     io::Write,
     path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime},
 };
 use syn::File;
-use witgen_macro_helper::{parse_crate_as_file, Resolver, Wit};
+use witgen_macro_helper::{
+    check_references, crate_source_files, include_referenced_types,
+    ir::{Document, Item, Order, UseName, UseNames},
+    parse_crate_as_file_with_cfg,
+    printer::Printer,
+    resolve_wit_file,
+    rust::RustPrinter,
+    Cfg, Diagnostics, Dialect, Interface, PackageName, Resolver, Wit,
+};
+
+const DEFAULT_OUTPUT: &str = "index.wit";
+
+/// How often `--watch` checks the files for changes
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+const PROLOGUE: &str = "// auto-generated file by witgen (https://github.com/bnjjj/witgen), please do not edit yourself, you can generate a new one thanks to cargo witgen generate command.";
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -27,6 +46,10 @@ pub enum Command {
     /// Generate wit files
     #[clap(alias = "gen")]
     Generate(Witgen),
+    /// Check that the output file is up to date, printing a diff otherwise
+    Check(Witgen),
+    /// Generate Rust definitions annotated with `#[witgen]` from a wit file
+    Import(Import),
 }
 
 #[derive(Debug, Args)]
@@ -42,9 +65,9 @@ pub struct Witgen {
     #[clap(long, short = 'd', default_value = ".")]
     pub input_dir: PathBuf,
 
-    /// Specify output file to generate wit definitions
-    #[clap(long, short = 'o', default_value = "index.wit")]
-    pub output: PathBuf,
+    /// Specify output file to generate wit definitions [default: index.wit]
+    #[clap(long, short = 'o')]
+    pub output: Option<PathBuf>,
 
     /// Specify prefix file to copy into top of the generated wit file
     #[clap(long, short = 'b')]
@@ -67,8 +90,70 @@ pub struct Witgen {
     #[clap(long)]
     pub skip_prologue: bool,
 
+    /// Wit dialect to generate, either `legacy` (wit-parser 0.2) or `component` (component model)
+    /// [default: legacy]
+    #[clap(long)]
+    pub dialect: Option<Dialect>,
+
+    /// Package declared by a component model document, e.g. `my-ns:my-crate@0.1.0`
+    ///
+    ///
+    /// Defaults to `<crate-dir>:<crate-dir>` using the name of the input directory
+    #[clap(long)]
+    pub wit_package: Option<PackageName>,
+
+    /// Order of the declarations, either `source` or `topological` (types before the items
+    /// using them) [default: source]
+    ///
+    ///
+    /// Items imported from dependencies are sorted by dependency name in both cases
+    #[clap(long)]
+    pub order: Option<Order>,
+
+    /// Split the output into one file per Rust module or per trait, either `module` or `trait`
+    ///
+    ///
+    /// The items of the crate root, and the ones which aren't split out, are written to the
+    /// output file. The other files are written next to it, named after their module path,
+    /// e.g. `api-users.wit`, or their trait, and `use` the types they need from each other.
+    #[clap(long)]
+    pub split: Option<Split>,
+
+    /// Regenerate the output whenever the crate sources or prefix files change
+    ///
+    ///
+    /// Errors are reported without exiting
+    #[clap(long)]
+    pub watch: bool,
+
+    /// Generate the items which could be converted even if others failed, instead of erroring
+    ///
+    ///
+    /// Failures are still reported on stderr
+    #[clap(long)]
+    pub allow_partial: bool,
+
+    /// Generate the structs, enums and type aliases used by the generated items, even when they
+    /// aren't annotated with `#[witgen]`
+    ///
+    ///
+    /// Same as annotating every item with `#[witgen(deep)]`
+    #[clap(long)]
+    pub deep: bool,
+
+    /// Target triple to evaluate `#[cfg(...)]` for, e.g. `wasm32-unknown-unknown`
+    ///
+    ///
+    /// Defaults to the host
+    #[clap(long)]
+    pub target: Option<String>,
+
     #[clap(flatten)]
     pub cargo: ClapCargo,
+
+    /// Settings only available in the configuration file, see [`Witgen::load_config`]
+    #[clap(skip)]
+    pub config: Config,
 }
 
 impl Witgen {
@@ -76,13 +161,22 @@ impl Witgen {
         Self {
             input: None,
             input_dir: path.to_path_buf(),
-            output: PathBuf::from("index.wit"),
+            output: None,
             prefix_file: vec![],
             prefix_string: vec![],
             stdout: false,
             cargo: ClapCargo::default(),
             skip_resolve: false,
             skip_prologue: true,
+            dialect: None,
+            wit_package: None,
+            order: None,
+            split: None,
+            allow_partial: false,
+            deep: false,
+            watch: false,
+            target: None,
+            config: Config::default(),
         }
     }
 
@@ -95,125 +189,815 @@ impl Witgen {
     #[allow(dead_code)]
     pub fn gen_static_from_path(path: &Path) -> Result<String> {
         let witgen = Witgen::from_path(path);
-        witgen.resolve(&witgen.generate_str(witgen.read_input()?)?)
+        witgen.generate()
     }
 
-    pub fn read_input(&self) -> Result<File> {
+    /// Fill the options which weren't given on the command line from the configuration of the
+    /// crate, if any
+    ///
+    /// Flags can only be turned on, e.g. `skip-prologue = true` can't be undone from the command
+    /// line.
+    pub fn load_config(&mut self) -> Result<()> {
+        let config = match Config::load(&self.input_dir)? {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        if self.output.is_none() {
+            self.output = config
+                .output
+                .as_ref()
+                .map(|output| self.input_dir.join(output));
+        }
+        if self.dialect.is_none() {
+            self.dialect = config.dialect.as_deref().map(str::parse).transpose()?;
+        }
+        if self.order.is_none() {
+            self.order = config.order.as_deref().map(str::parse).transpose()?;
+        }
+        if self.split.is_none() {
+            self.split = config.split.as_deref().map(str::parse).transpose()?;
+        }
+        if self.prefix_file.is_empty() {
+            self.prefix_file = config
+                .prefix_file
+                .iter()
+                .map(|path| self.input_dir.join(path))
+                .collect();
+        }
+        if self.prefix_string.is_empty() {
+            self.prefix_string = config.prefix_string.clone();
+        }
+        self.skip_resolve |= config.skip_resolve;
+        self.skip_prologue |= config.skip_prologue;
+        self.allow_partial |= config.allow_partial;
+        self.deep |= config.deep;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Load the configuration, unless workspace members are selected since each of them has its
+    /// own
+    fn with_config(mut self) -> Result<Self> {
+        if !self.selects_packages() {
+            self.load_config()?;
+        }
+        Ok(self)
+    }
+
+    /// Output file, [`DEFAULT_OUTPUT`] unless configured otherwise
+    pub fn output(&self) -> &Path {
+        self.output
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_OUTPUT))
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect.unwrap_or_default()
+    }
+
+    pub fn order(&self) -> Order {
+        self.order.unwrap_or_default()
+    }
+
+    /// Root file of the crate to generate wit definitions from
+    pub fn input_path(&self) -> PathBuf {
         // TODO: figure out how to avoid the clone()
-        let input = self
-            .input
+        self.input
             .as_ref()
-            .map_or_else(|| self.input_dir.join("src/lib.rs"), |i| i.clone());
+            .map_or_else(|| self.input_dir.join("src/lib.rs"), |i| i.clone())
+    }
 
+    pub fn read_input(&self) -> Result<File> {
+        let input = self.input_path();
         if !input.exists() {
             bail!("input {:?} doesn't exist", input);
         }
-        parse_crate_as_file(&input)
+        let mut file = parse_crate_as_file_with_cfg(&input, &self.cfg()?)?;
+        self.check_diagnostics(include_referenced_types(&mut file, self.deep), &input)?;
+        Ok(file)
+    }
+
+    /// Configuration `#[cfg(...)]` is evaluated with: the target and the enabled features
+    ///
+    /// Features are the ones given with `--features`, or all of them with `--all-features`, and
+    /// `default` unless `--no-default-features`, along with the features they enable in turn.
+    pub fn cfg(&self) -> Result<Cfg> {
+        let cfg = match &self.target {
+            Some(target) => Cfg::target(target)?,
+            None => Cfg::host(),
+        };
+        let declared = Config::cargo_features(&self.input_dir)?;
+        let features = &self.cargo.features;
+        let mut enabled: Vec<String> = if features.all_features {
+            declared.keys().cloned().collect()
+        } else {
+            features
+                .features
+                .iter()
+                .flat_map(|features| features.split(|c: char| c == ',' || c.is_whitespace()))
+                .filter(|feature| !feature.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        if !features.no_default_features && declared.contains_key("default") {
+            enabled.push("default".to_string());
+        }
+        let mut next = 0;
+        while next < enabled.len() {
+            let implied = declared.get(&enabled[next]).into_iter().flatten();
+            next += 1;
+            for feature in implied {
+                // Dependencies and their features don't gate items of this crate
+                if feature.starts_with("dep:") || feature.contains('/') {
+                    continue;
+                }
+                if !enabled.contains(feature) {
+                    enabled.push(feature.clone());
+                }
+            }
+        }
+        Ok(cfg.with_features(enabled))
     }
 
     pub fn generate_str(&self, file: File) -> Result<String> {
-        let wit: Wit = file.into();
-        let mut wit_str = if self.skip_prologue {
+        let prefixes = self.prefixes()?;
+        let doc = self.document(file, &prefixes)?;
+        Ok(format!(
+            "{}{prefixes}{}",
+            self.prologue(),
+            Printer::new(self.dialect()).print_document(&doc)?
+        ))
+    }
+
+    fn prologue(&self) -> String {
+        if self.skip_prologue {
             String::new()
         } else {
-            format!("// auto-generated file by witgen (https://github.com/bnjjj/witgen), please do not edit yourself, you can generate a new one thanks to cargo witgen generate command. (cargo-witgen v{}) \n\n", env!("CARGO_PKG_VERSION"))
-        };
+            format!(
+                "{PROLOGUE} (cargo-witgen v{}) \n\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        }
+    }
+
+    /// Prefix strings and the content of the prefix files
+    fn prefixes(&self) -> Result<String> {
+        let mut res = String::new();
         if !self.prefix_string.is_empty() {
-            wit_str.push_str(&self.prefix_string.join("\n"));
-            wit_str.push('\n');
+            res.push_str(&self.prefix_string.join("\n"));
+            res.push('\n');
         }
         for path in &self.prefix_file {
             let prefix_file = String::from_utf8(read(path)?)?;
-            wit_str.push_str(&prefix_file);
-            wit_str.push('\n');
+            res.push_str(&prefix_file);
+            res.push('\n');
+        }
+        Ok(res)
+    }
+
+    /// Document generated from `file`, with the dependencies inlined and the configuration
+    /// applied
+    ///
+    /// Types declared by the `prefixes` can be referenced as well.
+    fn document(&self, file: File, prefixes: &str) -> Result<Document> {
+        let wit = Wit::from(file.clone());
+        let (mut doc, diagnostics) = wit.to_document();
+        self.check_diagnostics(diagnostics, &self.input_path())?;
+        if !self.skip_resolve {
+            self.inline_deps(&WitResolver::new(&self.cargo), &wit, &mut doc)?;
+        }
+        self.config.apply(&mut doc)?;
+        doc.sort(self.order());
+        self.check_diagnostics(check_references(&file, &doc, prefixes), &self.input_path())?;
+        if self.dialect() == Dialect::Component {
+            doc.package = Some(self.package_name()?);
+        }
+        Ok(doc)
+    }
+
+    /// Fail on items which couldn't be converted, or only report them with `--allow-partial`
+    fn check_diagnostics(&self, mut diagnostics: Diagnostics, root: &Path) -> Result<()> {
+        if diagnostics.is_empty() {
+            return Ok(());
+        }
+        diagnostics.locate(root);
+        if !self.allow_partial {
+            bail!("{diagnostics}(use --allow-partial to generate the other items anyway)");
+        }
+        eprint!("{diagnostics}");
+        Ok(())
+    }
+
+    pub fn package_name(&self) -> Result<PackageName> {
+        if let Some(package) = &self.wit_package {
+            return Ok(package.clone());
+        }
+        if let Some(package) = &self.config.package {
+            let mut package: PackageName = package.parse()?;
+            if package.version.is_none() {
+                package.version = self.config.version.clone();
+            }
+            return Ok(package);
         }
-        wit_str.push_str(&wit.to_string());
-        Ok(wit_str)
+        let dir = self.input_dir.canonicalize()?;
+        let name = dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("cannot derive a package name from {:?}", dir))?;
+        PackageName::new(name, name, None)
+    }
+
+    /// Replace the `use` items of `doc` by the items they import from the dependencies,
+    /// along with the types these refer to
+    fn inline_deps(&self, resolver: &WitResolver, wit: &Wit, doc: &mut Document) -> Result<()> {
+        let mut deps: Vec<(String, Vec<UseNames>)> = vec![];
+        for import in wit.dependencies() {
+            match deps.iter_mut().find(|(from, _)| *from == import.from) {
+                Some((_, names)) => names.push(import.names),
+                None => deps.push((import.from, vec![import.names])),
+            }
+        }
+        deps.sort_by(|(a, _), (b, _)| a.cmp(b));
+        doc.retain(|item| !matches!(item, Item::Use(_)));
+        for (name, mut imports) in deps {
+            let dep_witgen = Witgen::from_path(&resolver.manifest_dir(&name)?);
+            let dep: Wit = dep_witgen.read_input()?.into();
+            let (mut dep_doc, diagnostics) = dep.to_document();
+            self.check_diagnostics(diagnostics, &dep_witgen.input_path())?;
+            self.inline_deps(resolver, &dep, &mut dep_doc)?;
+            // References use the kebab-cased Rust name, import renamed types under it
+            for (from, to) in dep.renames() {
+                for names in imports.iter_mut() {
+                    match names {
+                        UseNames::Glob => {}
+                        UseNames::Names(names) => {
+                            for import in names.iter_mut().filter(|import| import.name == from) {
+                                import.rename.get_or_insert_with(|| from.clone());
+                                import.name = to.clone();
+                            }
+                        }
+                    }
+                }
+                if imports.contains(&UseNames::Glob) {
+                    imports.push(UseNames::Names(vec![UseName {
+                        name: to,
+                        rename: Some(from),
+                    }]));
+                }
+            }
+            for names in &imports {
+                if let UseNames::Names(names) = names {
+                    if let Some(missing) = names.iter().find(|n| dep_doc.get(&n.name).is_none()) {
+                        bail!("`{}` is not generated by `{name}`", missing.name);
+                    }
+                }
+            }
+            let mut imported = dep_doc.import(&imports);
+            // Types imported from several crates are declared once
+            imported.retain(|item| !matches!(item.name(), Some(name) if doc.get(name).is_some()));
+            doc.merge(imported);
+        }
+        Ok(())
     }
 
     pub fn write_output(&self, wit_str: &str) -> Result<()> {
         if self.stdout {
             println!("{wit_str}");
         } else {
-            write_file(&self.output, wit_str)?;
+            write_file(self.output(), wit_str)?;
+        }
+        Ok(())
+    }
+
+    /// Write the files returned by [`Witgen::generate_files`], each one preceded by a comment
+    /// with its path on stdout
+    pub fn write_files(&self, files: &[(PathBuf, String)]) -> Result<()> {
+        if let [(_, wit_str)] = files {
+            return self.write_output(wit_str);
+        }
+        for (path, wit_str) in files {
+            if self.stdout {
+                println!("// {}\n{wit_str}", path.display());
+            } else {
+                write_file(path, wit_str)?;
+            }
         }
         Ok(())
     }
 
-    pub fn resolve_wit(&self, wit_str: &str) -> Result<HashMap<String, String>> {
+    /// Generate the content of the output file, which is checked with [`Witgen::validate`]
+    ///
+    /// `--split` is left out, see [`Witgen::generate_files`].
+    pub fn generate(&self) -> Result<String> {
+        let input = self.read_input()?;
+        let wit = Wit::from(input.clone());
+        let prefixes = self.prefixes()?;
+        let doc = self.document(input, &prefixes)?;
+        self.validate(&wit, &doc, &prefixes)?;
+        Ok(format!(
+            "{}{prefixes}{}",
+            self.prologue(),
+            Printer::new(self.dialect()).print_document(&doc)?
+        ))
+    }
+
+    /// Parse the generated wit, failing with the location of the Rust item which generated an
+    /// invalid definition
+    ///
+    /// Files imported with `use` are looked for next to the output, then generated from the
+    /// dependency of the same name. The bundled parser doesn't support `interface` blocks, so
+    /// each interface is checked on its own, see [`parsable_documents`]. It only knows the
+    /// legacy dialect, and partial output can reference the items left out: a warning reports
+    /// that these aren't checked.
+    pub fn validate(&self, wit: &Wit, doc: &Document, prefixes: &str) -> Result<()> {
+        self.validate_file(wit, self.output(), doc, prefixes, &[])
+    }
+
+    /// [`Witgen::validate`] the document written to `path` after `prefixes`, looking for the
+    /// files it `use`s among the `generated` ones first
+    fn validate_file(
+        &self,
+        wit: &Wit,
+        path: &Path,
+        doc: &Document,
+        prefixes: &str,
+        generated: &[(PathBuf, Document)],
+    ) -> Result<()> {
+        if self.dialect() != Dialect::Legacy {
+            eprintln!(
+                "warning: {} isn't validated, the parser only reads the legacy dialect",
+                path.display()
+            );
+            return Ok(());
+        }
+        if self.allow_partial {
+            eprintln!(
+                "warning: {} isn't validated, partial output can reference the items left out",
+                path.display()
+            );
+            return Ok(());
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("index");
+        let printer = Printer::new(Dialect::Legacy);
         let mut resolver = WitResolver::new(&self.cargo);
-        let _ = resolver.parse_wit_interface(
-            self.output.to_str().expect("failed to decode output"),
-            wit_str,
-        )?;
-        Ok(resolver.wit_generated)
+        for parsable in parsable_documents(doc) {
+            let wit_str = format!("{prefixes}{}", printer.print_document(&parsable)?);
+            let res = Interface::parse_with(name, &wit_str, |dep| {
+                if let Some((path, doc)) = generated
+                    .iter()
+                    .find(|(path, _)| path.file_stem() == Some(OsStr::new(dep)))
+                {
+                    return Ok((path.clone(), printer.print_document(&flattened(doc))?));
+                }
+                resolve_wit_file(dir, dep)
+                    .or_else(|_| Ok((PathBuf::from(dep), resolver.resolve_name(dep)?)))
+            });
+            let err = match res {
+                Ok(_) => continue,
+                Err(err) => err,
+            };
+            match wit.diagnose_output(&wit_str, &err) {
+                Some(mut diagnostic) => {
+                    diagnostic.locate(&self.input_path());
+                    bail!("generated wit is invalid:\n  {diagnostic}")
+                }
+                None => return Err(err.context("generated wit is invalid")),
+            }
+        }
+        Ok(())
     }
 
-    pub fn run(&self) -> Result<()> {
+    /// Generate the output file, or the files the output is split into with `--split`, along
+    /// with their paths
+    ///
+    /// Every split file is validated, see [`Witgen::validate`], and the `use`s of the prefixes
+    /// aren't resolved.
+    pub fn generate_files(&self) -> Result<Vec<(PathBuf, String)>> {
+        let split = match self.split {
+            Some(split) => split,
+            None => return Ok(vec![(self.output().to_path_buf(), self.generate()?)]),
+        };
         let input = self.read_input()?;
-        let mut wit_str = self.generate_str(input)?;
-        if !self.skip_resolve {
-            wit_str = self.resolve(&wit_str)?;
+        let wit = Wit::from(input.clone());
+        let modules = wit.declaring_modules();
+        let prefixes = self.prefixes()?;
+        let mut doc = self.document(input, &prefixes)?;
+        // Worlds are printed in the root file, along with their functions
+        doc.hoist_world_functions();
+        let output = self.output();
+        // Files `use` each other by interface name, which is the file name in the legacy
+        // dialect
+        let root = match &doc.package {
+            Some(package) => package.name.clone(),
+            None => output
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("index")
+                .to_string(),
+        };
+        if split == Split::Trait {
+            if let Some(Item::Interface(interface)) = doc.get(&root) {
+                bail!(
+                    "trait `{0}` can't be split out of the output, whose interface is named `{0}` as well",
+                    interface.name
+                );
+            }
+        }
+        let mut docs = doc.split(|item| match (split, item) {
+            _ if doc.is_world_function(None, item) => root.clone(),
+            (Split::Trait, Item::Interface(interface)) => interface.name.clone(),
+            (Split::Trait, _) => root.clone(),
+            (Split::Module, item) => {
+                let name = item.name().unwrap_or_default();
+                // The naming policy was applied to the types after they were generated
+                let module_path = modules.iter().find_map(|(declared, module_path)| {
+                    let declared = if item.is_type() {
+                        self.config.naming.type_name(declared)
+                    } else {
+                        declared.clone()
+                    };
+                    (declared == name).then_some(module_path)
+                });
+                match module_path {
+                    Some(module_path) if !module_path.is_empty() => {
+                        module_path.join("-").to_kebab_case()
+                    }
+                    _ => root.clone(),
+                }
+            }
+        });
+
+        match docs
+            .iter_mut()
+            .find(|split| split.name.as_ref() == Some(&root))
+        {
+            Some(root) => root.worlds = doc.worlds.clone(),
+            None if !doc.worlds.is_empty() => docs.push(Document {
+                package: doc.package.clone(),
+                name: Some(root.clone()),
+                worlds: doc.worlds.clone(),
+                ..Document::default()
+            }),
+            None => {}
+        }
+
+        let printer = Printer::new(self.dialect());
+        let dir = output.parent().unwrap_or_else(|| Path::new(""));
+        let mut generated = vec![];
+        for mut doc in docs {
+            let path = if doc.name.as_ref() == Some(&root) {
+                if self.dialect() == Dialect::Component {
+                    doc.name = None;
+                }
+                output.to_path_buf()
+            } else {
+                dir.join(format!("{}.wit", doc.name.as_deref().unwrap_or_default()))
+            };
+            generated.push((path, doc));
         }
-        self.write_output(&wit_str)
+        let mut files = vec![];
+        for (path, doc) in &generated {
+            // Only the output starts with the prefixes
+            let prefixes = if path == output {
+                prefixes.as_str()
+            } else {
+                ""
+            };
+            self.validate_file(&wit, path, doc, prefixes, &generated)?;
+            let wit_str = format!(
+                "{}{prefixes}{}",
+                self.prologue(),
+                printer.print_document(doc)?
+            );
+            files.push((path.clone(), wit_str));
+        }
+        Ok(files)
+    }
+
+    pub fn run(&self) -> Result<()> {
+        if self.selects_packages() {
+            return self.workspace_members()?.iter().try_for_each(Witgen::run);
+        }
+        self.write_files(&self.generate_files()?)
     }
 
-    pub fn resolve(&self, wit_str: &str) -> Result<String> {
-        let dep_wit = self
-            .resolve_wit(wit_str)?
-            .into_values()
-            .collect::<Vec<String>>()
-            .join("\n");
+    /// Whether `--workspace`, `--all` or `-p` select the workspace members to generate
+    pub fn selects_packages(&self) -> bool {
+        let workspace = &self.cargo.workspace;
+        workspace.workspace || workspace.all || !workspace.package.is_empty()
+    }
 
-        // remove `use` from file since combining
-        let re = Regex::new(r"^use .+\n").unwrap();
-        let mut res = re.replace_all(wit_str, "").to_string();
-        res.push_str(&dep_wit);
+    /// Generation of each selected workspace member declaring `#[witgen]` items
+    ///
+    /// Members are selected with `-p`, or all of them with `--workspace`, and filtered with
+    /// `--exclude`. The output of each member is relative to its directory.
+    pub fn workspace_members(&self) -> Result<Vec<Witgen>> {
+        // Every member would write to the same file
+        if let Some(output) = self.output.as_ref().filter(|output| output.is_absolute()) {
+            bail!(
+                "--output {} is absolute, it has to be relative to the members when selecting several",
+                output.display()
+            );
+        }
+        let metadata = self.cargo.metadata()?;
+        let workspace = &self.cargo.workspace;
+        let members: Vec<_> = metadata
+            .packages
+            .iter()
+            .filter(|package| metadata.workspace_members.contains(&package.id))
+            .collect();
+        for name in workspace.package.iter().chain(&workspace.exclude) {
+            if !members.iter().any(|package| &package.name == name) {
+                bail!("`{name}` is not a member of the workspace");
+            }
+        }
+        let mut res = vec![];
+        for package in members {
+            if (!workspace.package.is_empty() && !workspace.package.contains(&package.name))
+                || workspace.exclude.contains(&package.name)
+            {
+                continue;
+            }
+            let dir = package.manifest_path.parent().map_or_else(
+                || bail!("failed to find parent of {}", package.manifest_path),
+                Ok,
+            )?;
+            let mut member = self.for_member(dir.as_std_path());
+            member.load_config()?;
+            member
+                .output
+                .get_or_insert_with(|| dir.as_std_path().join(DEFAULT_OUTPUT));
+            if member.input_path().exists() && !Wit::from(member.read_input()?).flatten().is_empty()
+            {
+                res.push(member);
+            }
+        }
         Ok(res)
     }
+
+    /// Same options for the crate in `dir`
+    fn for_member(&self, dir: &Path) -> Self {
+        let mut member = Witgen::from_path(dir);
+        member.output = self.output.as_ref().map(|output| dir.join(output));
+        member.prefix_file = self.prefix_file.clone();
+        member.prefix_string = self.prefix_string.clone();
+        member.stdout = self.stdout;
+        member.skip_resolve = self.skip_resolve;
+        member.skip_prologue = self.skip_prologue;
+        member.dialect = self.dialect;
+        member.order = self.order;
+        member.split = self.split;
+        member.allow_partial = self.allow_partial;
+        member.deep = self.deep;
+        member.target = self.target.clone();
+        member.cargo.features = self.cargo.features.clone();
+        member.cargo.manifest.manifest_path = Some(dir.join("Cargo.toml"));
+        member
+    }
+
+    /// Files the output depends on: the crate sources, following `mod` declarations, and the
+    /// prefix files
+    pub fn watched_files(&self) -> Vec<PathBuf> {
+        let mut files = crate_source_files(&self.input_path());
+        files.extend(self.prefix_file.iter().cloned());
+        files
+    }
+
+    /// Regenerate the output whenever one of the files it depends on changes
+    ///
+    /// The files are polled every [`WATCH_INTERVAL`] and listed again after each change, so
+    /// that new modules are picked up. The output is only written when its content changes.
+    pub fn watch(&self) -> Result<()> {
+        if self.selects_packages() {
+            bail!("--watch can only watch a single crate");
+        }
+        let mut last_changes = None;
+        let mut last_output = None;
+        loop {
+            let changes: Vec<(PathBuf, Option<SystemTime>)> = self
+                .watched_files()
+                .into_iter()
+                .map(|path| {
+                    let modified = metadata(&path).and_then(|meta| meta.modified()).ok();
+                    (path, modified)
+                })
+                .collect();
+            if last_changes.as_ref() != Some(&changes) {
+                last_changes = Some(changes);
+                match self.generate_files() {
+                    Ok(files) if last_output.as_ref() != Some(&files) => {
+                        self.write_files(&files)?;
+                        if !self.stdout {
+                            for (path, _) in &files {
+                                eprintln!("Generated {:?}", path);
+                            }
+                        }
+                        last_output = Some(files);
+                    }
+                    Ok(_) => {}
+                    Err(err) => eprintln!("Error: {err:#}"),
+                }
+            }
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+
+    /// Unified diff between the output files and what would be generated, if they differ
+    ///
+    /// The prologue is ignored since it contains the version of cargo-witgen, and a missing file
+    /// is compared as empty.
+    pub fn diff(&self) -> Result<Option<String>> {
+        let mut res = String::new();
+        for (path, generated) in self.generate_files()? {
+            let current = if path.exists() {
+                read_to_string(&path).with_context(|| format!("cannot read {:?}", path))?
+            } else {
+                String::new()
+            };
+            let (current, generated) = (strip_prologue(&current), strip_prologue(&generated));
+            if current == generated {
+                continue;
+            }
+            let path = path.display().to_string();
+            res.push_str(
+                &TextDiff::from_lines(current, generated)
+                    .unified_diff()
+                    .header(&path, &format!("{path} (generated)"))
+                    .to_string(),
+            );
+        }
+        Ok((!res.is_empty()).then_some(res))
+    }
+
+    /// Fail with a diff when the output file is out of date
+    ///
+    /// `--watch` and `--stdout` only apply to `generate` and are refused. With workspace members,
+    /// the ones which failed to generate are reported apart from the ones out of date.
+    pub fn check(&self) -> Result<()> {
+        if self.watch || self.stdout {
+            bail!("`--watch` and `--stdout` can only be used with `cargo witgen generate`");
+        }
+        if !self.selects_packages() {
+            if let Some(diff) = self.diff()? {
+                print!("{diff}");
+                bail!(
+                    "{:?} is out of date, run `cargo witgen generate` to update it",
+                    self.output()
+                );
+            }
+            return Ok(());
+        }
+        let (mut failed, mut stale) = (0, 0);
+        for member in self.workspace_members()? {
+            match member.diff() {
+                Ok(None) => {}
+                Ok(Some(diff)) => {
+                    print!("{diff}");
+                    eprintln!("Error: {:?} is out of date", member.output());
+                    stale += 1;
+                }
+                Err(err) => {
+                    eprintln!("Error: {err:#}");
+                    failed += 1;
+                }
+            }
+        }
+        match (failed, stale) {
+            (0, 0) => Ok(()),
+            (0, stale) => bail!(
+                "{stale} workspace members are out of date, run `cargo witgen generate` to update them"
+            ),
+            (failed, 0) => bail!("{failed} workspace members failed to generate"),
+            (failed, stale) => bail!(
+                "{failed} workspace members failed to generate and {stale} are out of date"
+            ),
+        }
+    }
 }
 
 struct WitResolver<'a> {
     cargo: &'a ClapCargo,
-    wit_generated: HashMap<String, String>,
 }
 
 impl<'a> WitResolver<'a> {
     fn new(cargo: &'a ClapCargo) -> Self {
-        Self {
-            cargo,
-            wit_generated: Default::default(),
-        }
+        Self { cargo }
     }
-}
 
-impl Resolver for WitResolver<'_> {
-    fn resolve_name(&mut self, name: &str) -> Result<String> {
-        let package = self
-            .cargo
-            .find_package(name)?
-            .or_else(|| {
-                self.cargo
-                    .find_package(&name.to_kebab_case())
-                    .unwrap_or(None)
-            })
-            .map_or_else(|| bail!("Failed to find {name}"), Ok)?;
+    fn manifest_dir(&self, name: &str) -> Result<PathBuf> {
+        // `use` paths name the crate by its Rust ident, which cargo only reports as similar to
+        // the kebab-cased package name
+        let package = match self.cargo.find_package(&name.to_kebab_case()) {
+            Ok(Some(package)) => package,
+            _ => self
+                .cargo
+                .find_package(name)?
+                .map_or_else(|| bail!("Failed to find {name}"), Ok)?,
+        };
 
         let manifest_dir = package.manifest_path.as_std_path().parent().map_or_else(
             || bail!("failed to find parent of {}", package.manifest_path),
             Ok,
         )?;
+        Ok(manifest_dir.to_path_buf())
+    }
+}
 
-        let res = Witgen::gen_from_path(manifest_dir)?;
-        self.wit_generated.insert(name.to_string(), res.clone());
-        Ok(res)
+impl Resolver for WitResolver<'_> {
+    fn resolve_name(&mut self, name: &str) -> Result<String> {
+        let manifest_dir = self.manifest_dir(name)?;
+        Witgen::gen_from_path(&manifest_dir)
     }
 }
 
-impl Command {
+#[derive(Debug, Args)]
+pub struct Import {
+    /// Wit file to generate Rust definitions from
+    ///
+    ///
+    /// Files it `use`s are looked for in the same directory
+    pub file: PathBuf,
+
+    /// Specify output file to write the Rust definitions to, instead of stdout
+    #[clap(long, short = 'o')]
+    pub output: Option<PathBuf>,
+}
+
+impl Import {
+    /// Rust source which generates the interface of the wit file back
+    pub fn generate(&self) -> Result<String> {
+        let source =
+            read_to_string(&self.file).with_context(|| format!("cannot read {:?}", self.file))?;
+        let name = self
+            .file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .context("invalid wit file name")?;
+        let dir = self.file.parent().unwrap_or_else(|| Path::new("."));
+        let interface = Interface::parse_with(name, &source, |dep| resolve_wit_file(dir, dep))
+            .with_context(|| format!("cannot parse {:?}", self.file))?;
+        RustPrinter.print_document(&Document::from_interface(&interface)?)
+    }
+
     pub fn run(&self) -> Result<()> {
+        let rust = self.generate()?;
+        match &self.output {
+            Some(output) => write_file(output, &rust),
+            None => {
+                print!("{rust}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How `--split` spreads the generated items over several files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    /// One file per Rust module declaring items
+    Module,
+    /// One file per `#[witgen]` trait
+    Trait,
+}
+
+impl FromStr for Split {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "module" => Ok(Split::Module),
+            "trait" => Ok(Split::Trait),
+            other => bail!("unknown split '{other}', expected 'module' or 'trait'"),
+        }
+    }
+}
+
+impl Display for Split {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Split::Module => write!(f, "module"),
+            Split::Trait => write!(f, "trait"),
+        }
+    }
+}
+
+impl Command {
+    pub fn run(self) -> Result<()> {
         match self {
-            Command::Generate(witgen) => witgen.run()?,
+            Command::Generate(witgen) => {
+                let witgen = witgen.with_config()?;
+                if witgen.watch {
+                    witgen.watch()?
+                } else {
+                    witgen.run()?
+                }
+            }
+            Command::Check(witgen) => witgen.with_config()?.check()?,
+            Command::Import(import) => import.run()?,
         };
         Ok(())
     }
@@ -221,11 +1005,64 @@ impl Command {
 
 impl App {
     #[allow(dead_code)]
-    pub fn run(&self) -> Result<()> {
+    pub fn run(self) -> Result<()> {
         self.command.run()
     }
 }
 
+/// Documents the bundled parser can read in place of `doc`, which it can't when `doc` declares
+/// interfaces: the items outside of them, then each interface with its items declared at the
+/// top level, next to the outer items it doesn't shadow
+fn parsable_documents(doc: &Document) -> Vec<Document> {
+    let (interfaces, outer): (Vec<&Item>, Vec<&Item>) = doc
+        .items
+        .iter()
+        .partition(|item| matches!(item, Item::Interface(_)));
+    let mut res = vec![Document::new(
+        outer.iter().map(|item| (*item).clone()).collect(),
+    )];
+    for item in interfaces {
+        if let Item::Interface(interface) = item {
+            let declared: Vec<&str> = interface.items.iter().filter_map(Item::name).collect();
+            let mut items: Vec<Item> = outer
+                .iter()
+                .filter(|item| !matches!(item.name(), Some(name) if declared.contains(&name)))
+                .map(|item| (*item).clone())
+                .collect();
+            items.extend(interface.items.iter().cloned());
+            res.push(Document::new(items));
+        }
+    }
+    res
+}
+
+/// `doc` with the items of its interfaces declared at the top level, for the documents
+/// `use`ing it to be parsed, the first declaration of a name winning
+fn flattened(doc: &Document) -> Document {
+    let mut res = Document::default();
+    for item in &doc.items {
+        let items = match item {
+            Item::Interface(interface) => interface.items.iter().collect(),
+            item => vec![item],
+        };
+        for item in items {
+            if !matches!(item.name(), Some(name) if res.get(name).is_some()) {
+                res.items.push(item.clone());
+            }
+        }
+    }
+    res
+}
+
+/// `wit` without the prologue line and the blank line following it
+fn strip_prologue(wit: &str) -> &str {
+    if !wit.starts_with(PROLOGUE) {
+        return wit;
+    }
+    let rest = wit.split_once('\n').map_or("", |(_, rest)| rest);
+    rest.strip_prefix('\n').unwrap_or(rest)
+}
+
 fn write_file(path: &Path, contents: &str) -> Result<()> {
     let mut file = OpenOptions::new()
         .write(true)
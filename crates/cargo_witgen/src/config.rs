@@ -0,0 +1,158 @@
+//! Defaults read from `witgen.toml` or the `[package.metadata.witgen]` table of `Cargo.toml`.
+
+use anyhow::{Context, Result};
+use heck::ToKebabCase;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+use witgen_macro_helper::ir::{Document, Item, Type};
+
+/// Project configuration, every setting being optional
+///
+/// ```toml
+/// [package.metadata.witgen]
+/// output = "wit/index.wit"
+/// dialect = "component"
+/// order = "topological"
+/// package = "my-ns:my-crate"
+/// version = "0.1.0"
+///
+/// [package.metadata.witgen.types]
+/// Uuid = "string"
+///
+/// [package.metadata.witgen.naming]
+/// type-prefix = "my-"
+/// strip-suffix = ["Dto"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Relative to the directory of the configuration
+    pub output: Option<PathBuf>,
+    /// Relative to the directory of the configuration
+    pub prefix_file: Vec<PathBuf>,
+    pub prefix_string: Vec<String>,
+    pub skip_resolve: bool,
+    pub skip_prologue: bool,
+    pub allow_partial: bool,
+    /// Generate the types used by the generated items without annotating them
+    pub deep: bool,
+    /// `legacy` or `component`
+    pub dialect: Option<String>,
+    /// `source` or `topological`
+    pub order: Option<String>,
+    /// `module` or `trait`
+    pub split: Option<String>,
+    /// Package of a component model document, e.g. `my-ns:my-crate`
+    pub package: Option<String>,
+    /// Version of the package, unless `package` has one already
+    pub version: Option<String>,
+    /// Wit type to use for each reference to a Rust type, e.g. `Uuid = "string"`
+    pub types: BTreeMap<String, String>,
+    pub naming: Naming,
+}
+
+/// How type names are derived from the Rust ones, on top of kebab-casing them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Naming {
+    /// Prepended to every type name
+    pub type_prefix: Option<String>,
+    /// Removed from the end of type names which have one of them, e.g. `Dto` for `UserDto`
+    pub strip_suffix: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: Option<ManifestPackage>,
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct ManifestPackage {
+    metadata: Option<ManifestMetadata>,
+}
+
+#[derive(Deserialize)]
+struct ManifestMetadata {
+    witgen: Option<Config>,
+}
+
+impl Config {
+    /// Configuration of the crate in `dir`, `witgen.toml` taking precedence over `Cargo.toml`
+    pub fn load(dir: &Path) -> Result<Option<Config>> {
+        let path = dir.join("witgen.toml");
+        if path.exists() {
+            let config = toml::from_str(&read_to_string(&path)?)
+                .with_context(|| format!("invalid witgen configuration in {:?}", path))?;
+            return Ok(Some(config));
+        }
+        let path = dir.join("Cargo.toml");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let manifest: Manifest = toml::from_str(&read_to_string(&path)?)
+            .with_context(|| format!("invalid witgen configuration in {:?}", path))?;
+        Ok(manifest
+            .package
+            .and_then(|package| package.metadata)
+            .and_then(|metadata| metadata.witgen))
+    }
+
+    /// `[features]` table of the manifest in `dir`, empty without a manifest
+    pub fn cargo_features(dir: &Path) -> Result<BTreeMap<String, Vec<String>>> {
+        let path = dir.join("Cargo.toml");
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let manifest: Manifest = toml::from_str(&read_to_string(&path)?)
+            .with_context(|| format!("invalid manifest {:?}", path))?;
+        Ok(manifest.features)
+    }
+
+    /// Apply the type overrides and the naming policy to `doc`
+    pub fn apply(&self, doc: &mut Document) -> Result<()> {
+        for (rust_name, wit_type) in &self.types {
+            let ty: Type = wit_type
+                .parse()
+                .with_context(|| format!("invalid type override for `{rust_name}`"))?;
+            let name = rust_name.to_kebab_case();
+            doc.retain(|item| !item.is_type() || item.name() != Some(&name));
+            for item in doc.items.iter_mut() {
+                if let Item::Interface(interface) = item {
+                    interface
+                        .items
+                        .retain(|item| !item.is_type() || item.name() != Some(&name));
+                }
+            }
+            doc.replace_type(&name, &ty);
+        }
+        let names: Vec<String> = doc.all_type_names().into_iter().map(String::from).collect();
+        for name in names {
+            let renamed = self.naming.type_name(&name);
+            if renamed != name {
+                doc.rename(&name, &renamed);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Naming {
+    /// Wit name of the type which would be named `name` otherwise
+    pub fn type_name(&self, name: &str) -> String {
+        let stripped = self
+            .strip_suffix
+            .iter()
+            .find_map(|suffix| name.strip_suffix(&format!("-{}", suffix.to_kebab_case())))
+            .unwrap_or(name);
+        match &self.type_prefix {
+            Some(prefix) => format!("{prefix}{stripped}"),
+            None => stripped.to_string(),
+        }
+    }
+}
@@ -1,2 +1,4 @@
 mod app;
-pub use app::Witgen;
+mod config;
+pub use app::{Import, Split, Witgen};
+pub use config::{Config, Naming};
@@ -7,6 +7,7 @@ use clap::{crate_version, FromArgMatches, IntoApp};
 use std::env;
 
 mod app;
+mod config;
 use crate::app::App;
 
 fn main() -> Result<()> {
@@ -1,11 +1,14 @@
 #![deny(warnings)]
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::ItemImpl;
-use witgen_macro_helper::visitor::ImplVisitor;
+use syn::Item;
+use witgen_macro_helper::{
+    visitor::{ImplVisitor, WitgenAttrVisitor},
+    Wit, WitgenAttrs,
+};
 
 /// Proc macro attribute to help cargo-witgen to generate right definitions in `.wit` file
-/// ```no_run
+/// ```rust,ignore
 /// use witgen::witgen;
 ///
 /// #[witgen]
@@ -26,12 +29,29 @@ use witgen_macro_helper::visitor::ImplVisitor;
 /// }
 /// ```
 #[proc_macro_attribute]
-pub fn witgen(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    if let Ok(mut input) = syn::parse::<ItemImpl>(item.clone()) {
+pub fn witgen(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut errors = vec![];
+    let args = proc_macro2::TokenStream::from(attr);
+    if let Err(err) = WitgenAttrs::parse_args(args.clone()) {
+        errors.push(err.to_compile_error());
+    }
+    let mut input = match syn::parse::<Item>(item.clone()) {
+        Ok(input) => input,
+        Err(_) => {
+            let item = proc_macro2::TokenStream::from(item);
+            return quote! {#(#errors)* #item}.into();
+        }
+    };
+    // Report what can't be converted to wit at compile time, pointing at the offending tokens.
+    // The item is kept to avoid follow-up errors where it is used.
+    if let Err(err) = Wit::check_item(args, input.clone()) {
+        errors.push(err.to_compile_error());
+    }
+    // `#[witgen(...)]` on fields, variants, arguments and methods are only meant for witgen
+    WitgenAttrVisitor::strip_attrs(&mut input);
+    if let Item::Impl(input) = &mut input {
         // This converts attributes paths, e.g. #[path_macro], into a doc string, e.g. ///@path_macro
-        ImplVisitor::path_attrs_to_docs(&mut input);
-        quote! {#input}.into()
-    } else {
-        item
+        ImplVisitor::path_attrs_to_docs(input);
     }
+    quote! {#(#errors)* #input}.into()
 }
@@ -0,0 +1,137 @@
+use heck::ToKebabCase;
+use proc_macro2::{Ident, TokenStream};
+use syn::{parse::Parser, punctuated::Punctuated, Attribute, Lit, Meta, NestedMeta, Token};
+
+use crate::util::wit_ident;
+
+/// Arguments of `#[witgen(...)]`
+///
+/// They are given to the proc macro on items, e.g. `#[witgen(rename = "foo")] struct Bar {..}`,
+/// and can annotate fields, enum variants, fn arguments and methods as well.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WitgenAttrs {
+    /// Name to use in wit instead of the kebab-cased Rust identifier
+    pub rename: Option<String>,
+    /// `flags`: generate a struct of `bool` fields as wit `flags`
+    pub flags: bool,
+    /// Name of the generic instantiation a type alias refers to, e.g.
+    /// `#[witgen(name = "users")] type Users = Page<User>;`
+    pub name: Option<String>,
+    /// `deep`: also generate the types the item uses which aren't annotated, see
+    /// [`crate::include_referenced_types`]
+    pub deep: bool,
+    /// `import`: import the function, trait, impl or module into a world, see
+    /// [`crate::Wit::world`]
+    pub import: bool,
+    /// `export`: export the function, trait, impl or module from a world
+    pub export: bool,
+    /// World to import into or export from, e.g. `#[witgen(world = "cli")]`, instead of the
+    /// default one
+    pub world: Option<String>,
+    /// `constructor`: generate a function of a resource `impl` as its constructor
+    pub constructor: bool,
+}
+
+impl WitgenAttrs {
+    /// Parse the arguments given to the proc macro
+    pub fn parse_args(tokens: TokenStream) -> syn::Result<Self> {
+        let args = Punctuated::<NestedMeta, Token![,]>::parse_terminated.parse2(tokens)?;
+        Self::from_nested(args)
+    }
+
+    /// Arguments of every `#[witgen(...)]` in `attrs`
+    pub fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut res = Self::default();
+        for attr in attrs.iter().filter(|attr| is_witgen_attr(attr)) {
+            if let Meta::List(list) = attr.parse_meta()? {
+                res.merge(Self::from_nested(list.nested)?);
+            }
+        }
+        Ok(res)
+    }
+
+    fn from_nested(nested: impl IntoIterator<Item = NestedMeta>) -> syn::Result<Self> {
+        let mut res = Self::default();
+        for meta in nested {
+            match &meta {
+                NestedMeta::Meta(Meta::NameValue(name_value))
+                    if name_value.path.is_ident("rename") =>
+                {
+                    res.rename = Some(parse_name(&name_value.lit)?);
+                }
+                NestedMeta::Meta(Meta::NameValue(name_value))
+                    if name_value.path.is_ident("name") =>
+                {
+                    res.name = Some(parse_name(&name_value.lit)?);
+                }
+                NestedMeta::Meta(Meta::NameValue(name_value))
+                    if name_value.path.is_ident("world") =>
+                {
+                    res.world = Some(parse_name(&name_value.lit)?);
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("flags") => res.flags = true,
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("deep") => res.deep = true,
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("import") => res.import = true,
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("export") => res.export = true,
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("constructor") => {
+                    res.constructor = true
+                }
+                _ => return Err(syn::Error::new_spanned(meta, "unknown witgen argument")),
+            }
+        }
+        Ok(res)
+    }
+
+    fn merge(&mut self, other: Self) {
+        if other.rename.is_some() {
+            self.rename = other.rename;
+        }
+        self.flags |= other.flags;
+        self.deep |= other.deep;
+        self.import |= other.import;
+        self.export |= other.export;
+        self.constructor |= other.constructor;
+        if other.name.is_some() {
+            self.name = other.name;
+        }
+        if other.world.is_some() {
+            self.world = other.world;
+        }
+    }
+
+    /// Wit name of an item, field, variant or argument named `ident` in Rust
+    pub fn name(&self, ident: &Ident) -> anyhow::Result<String> {
+        match &self.rename {
+            Some(rename) => Ok(rename.clone()),
+            None => Ok(wit_ident(ident)),
+        }
+    }
+}
+
+/// Wit name of `ident` using the `#[witgen(...)]` arguments in `attrs`
+pub(crate) fn wit_name(ident: &Ident, attrs: &[Attribute]) -> anyhow::Result<String> {
+    WitgenAttrs::from_attrs(attrs)?.name(ident)
+}
+
+/// `#[witgen]` or `#[witgen::witgen]`, with or without arguments
+pub fn is_witgen_attr(attr: &Attribute) -> bool {
+    attr.path
+        .segments
+        .last()
+        .map_or(false, |segment| segment.ident == "witgen")
+}
+
+/// A name given as argument must already be a valid wit identifier
+fn parse_name(lit: &Lit) -> syn::Result<String> {
+    let name = match lit {
+        Lit::Str(name) => name.value(),
+        _ => return Err(syn::Error::new_spanned(lit, "expected a string")),
+    };
+    if name.is_empty() || name.to_kebab_case() != name {
+        return Err(syn::Error::new(
+            lit.span(),
+            format!("'{name}' is not a valid wit identifier, use kebab-case"),
+        ));
+    }
+    Ok(name)
+}
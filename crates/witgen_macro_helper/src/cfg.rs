@@ -0,0 +1,203 @@
+//! Evaluation of `#[cfg(...)]` predicates against a feature set and a target.
+
+use anyhow::{bail, Result};
+use syn::{Attribute, Lit, Meta, NestedMeta};
+
+/// Configuration `cfg` predicates are evaluated against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    /// Enabled cargo features
+    pub features: Vec<String>,
+    /// Options set on their own, e.g. `unix`
+    pub names: Vec<String>,
+    /// Options with a value, e.g. `target_os = "linux"`
+    pub options: Vec<(String, String)>,
+}
+
+impl Default for Cfg {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+impl Cfg {
+    /// Target witgen runs on, without any feature
+    pub fn host() -> Self {
+        let mut names = vec![];
+        if cfg!(unix) {
+            names.push("unix".to_string());
+        }
+        if cfg!(windows) {
+            names.push("windows".to_string());
+        }
+        let pointer_width = (std::mem::size_of::<usize>() * 8).to_string();
+        Self {
+            features: vec![],
+            names,
+            options: vec![
+                ("target_os".to_string(), std::env::consts::OS.to_string()),
+                (
+                    "target_family".to_string(),
+                    std::env::consts::FAMILY.to_string(),
+                ),
+                (
+                    "target_arch".to_string(),
+                    std::env::consts::ARCH.to_string(),
+                ),
+                ("target_pointer_width".to_string(), pointer_width),
+            ],
+        }
+    }
+
+    /// Target described by a triple such as `wasm32-unknown-unknown`, without any feature
+    pub fn target(triple: &str) -> Result<Self> {
+        let parts: Vec<&str> = triple.split('-').collect();
+        let arch = match parts.first() {
+            Some(arch) if !arch.is_empty() => *arch,
+            _ => bail!("invalid target triple '{triple}'"),
+        };
+        let os = match parts.as_slice() {
+            [_, _, "darwin", ..] => "macos",
+            [_, _, os, ..] if *os != "unknown" => os,
+            [_, os] if *os != "unknown" => os,
+            _ => "none",
+        };
+        let env = match parts.as_slice() {
+            [_, _, _, env] => Some(*env),
+            _ => None,
+        };
+        let family = match (arch, os) {
+            (_, "windows") => Some("windows"),
+            ("wasm32" | "wasm64", _) => Some("wasm"),
+            (_, "linux" | "macos" | "ios" | "android" | "freebsd" | "netbsd" | "openbsd") => {
+                Some("unix")
+            }
+            _ => None,
+        };
+        let pointer_width = match arch {
+            "x86_64" | "aarch64" | "wasm64" | "powerpc64" | "riscv64gc" | "s390x" => "64",
+            "avr" | "msp430" => "16",
+            _ => "32",
+        };
+
+        let mut options = vec![
+            ("target_os".to_string(), os.to_string()),
+            ("target_arch".to_string(), arch.to_string()),
+            (
+                "target_pointer_width".to_string(),
+                pointer_width.to_string(),
+            ),
+        ];
+        if let Some(env) = env {
+            options.push(("target_env".to_string(), env.to_string()));
+        }
+        let mut names = vec![];
+        if let Some(family) = family {
+            options.push(("target_family".to_string(), family.to_string()));
+            if family != "wasm" {
+                names.push(family.to_string());
+            }
+        }
+        Ok(Self {
+            features: vec![],
+            names,
+            options,
+        })
+    }
+
+    pub fn with_features(mut self, features: impl IntoIterator<Item = String>) -> Self {
+        self.features.extend(features);
+        self
+    }
+
+    /// Evaluate a predicate such as `all(unix, feature = "foo")`
+    ///
+    /// Unknown options are unset, as they are for the compiler.
+    pub fn eval(&self, predicate: &NestedMeta) -> Result<bool> {
+        let meta = match predicate {
+            NestedMeta::Meta(meta) => meta,
+            NestedMeta::Lit(lit) => bail!("unexpected literal in cfg predicate: {lit:?}"),
+        };
+        Ok(match meta {
+            Meta::Path(path) => match path.get_ident() {
+                Some(ident) => self.names.iter().any(|name| ident == name),
+                None => false,
+            },
+            Meta::NameValue(name_value) => {
+                let value = match &name_value.lit {
+                    Lit::Str(value) => value.value(),
+                    _ => bail!("cfg option values must be strings"),
+                };
+                match name_value.path.get_ident() {
+                    Some(ident) if ident == "feature" => self.features.contains(&value),
+                    Some(ident) => self
+                        .options
+                        .iter()
+                        .any(|(key, val)| ident == key && *val == value),
+                    None => false,
+                }
+            }
+            Meta::List(list) => {
+                let mut predicates = list.nested.iter().map(|nested| self.eval(nested));
+                match list.path.get_ident() {
+                    Some(ident) if ident == "all" => predicates
+                        .collect::<Result<Vec<bool>>>()?
+                        .into_iter()
+                        .all(|b| b),
+                    Some(ident) if ident == "any" => predicates
+                        .collect::<Result<Vec<bool>>>()?
+                        .into_iter()
+                        .any(|b| b),
+                    Some(ident) if ident == "not" && list.nested.len() == 1 => {
+                        !predicates.next().unwrap()?
+                    }
+                    _ => bail!("unknown cfg predicate `{}`", quote::quote!(#list)),
+                }
+            }
+        })
+    }
+
+    /// Whether every `#[cfg(...)]` of `attrs` holds
+    pub fn is_enabled(&self, attrs: &[Attribute]) -> Result<bool> {
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("cfg")) {
+            let enabled = match attr.parse_meta()? {
+                Meta::List(list) if list.nested.len() == 1 => self.eval(&list.nested[0])?,
+                _ => bail!("expected `#[cfg(predicate)]`"),
+            };
+            if !enabled {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Replace the `#[cfg_attr(predicate, attrs..)]` of `attrs` by `attrs..` when the predicate
+    /// holds, or remove them
+    pub fn expand_cfg_attrs(&self, attrs: &mut Vec<Attribute>) -> Result<()> {
+        let mut res = Vec::with_capacity(attrs.len());
+        for attr in attrs.drain(..) {
+            if !attr.path.is_ident("cfg_attr") {
+                res.push(attr);
+                continue;
+            }
+            let list = match attr.parse_meta() {
+                Ok(Meta::List(list)) if list.nested.len() >= 2 => list,
+                // Left for the compiler to report
+                _ => {
+                    res.push(attr);
+                    continue;
+                }
+            };
+            let mut nested = list.nested.into_iter();
+            if self.eval(&nested.next().unwrap())? {
+                for meta in nested {
+                    let mut expanded: Vec<Attribute> = vec![syn::parse_quote!(#[#meta])];
+                    self.expand_cfg_attrs(&mut expanded)?;
+                    res.extend(expanded);
+                }
+            }
+        }
+        *attrs = res;
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+//! Generation of the types used by annotated items without annotating them.
+//!
+//! Starting from the items annotated with `#[witgen(deep)]`, or every annotated item, the
+//! structs, enums and type aliases their types refer to are found in the crate and annotated
+//! with `#[witgen]`, as are the ones these refer to in turn.
+
+use anyhow::anyhow;
+use quote::ToTokens;
+use std::collections::HashMap;
+use syn::{
+    parse_quote,
+    spanned::Spanned,
+    visit::{self, Visit},
+    Attribute, File, FnArg, Generics, Item, Path, ReturnType, Signature, TraitItem, TypePath,
+    UseTree,
+};
+
+use crate::{
+    attrs::is_witgen_attr,
+    diagnostics::{Diagnostic, Diagnostics},
+    generics::type_params,
+    util::pub_method,
+    wit::BUILTIN_TYPES,
+    WitgenAttrs,
+};
+
+/// Annotate with `#[witgen]` the types used by the items annotated with `#[witgen(deep)]`, or
+/// by every annotated item when `all` is set
+///
+/// Types are resolved like Rust does, from the module of the item using them and the `use`
+/// items in scope, then by name in all the modules of the crate. Those which can't be found are
+/// left to be reported as unresolved, and those which can't be represented are reported like
+/// any annotated item. Names which could refer to several types are reported.
+pub fn include_referenced_types(file: &mut File, all: bool) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    let mut modules = HashMap::new();
+    let mut queue = vec![];
+    collect(
+        &file.items,
+        &mut vec![],
+        &mut vec![],
+        all,
+        &mut modules,
+        &mut queue,
+    );
+    let scopes = Scopes { modules };
+    let mut included: Vec<Vec<usize>> = vec![];
+    while let Some((path, module_path)) = queue.pop() {
+        let item = item_at(&mut file.items, &path);
+        let mut visitor = TypeNameVisitor {
+            params: vec![],
+            paths: vec![],
+        };
+        visit_types(item, &mut visitor);
+        for type_path in visitor.paths {
+            match scopes.resolve(&module_path, &type_path) {
+                Resolution::Found(declaration)
+                    if !declaration.annotated && !included.contains(&declaration.path) =>
+                {
+                    included.push(declaration.path.clone());
+                    queue.push((declaration.path, declaration.module_path));
+                }
+                Resolution::Ambiguous(candidates) => {
+                    let err = anyhow!(
+                        "`{}` is ambiguous, it could refer to `{}`",
+                        type_path.to_token_stream(),
+                        candidates.join("` or `")
+                    );
+                    diagnostics.push(Diagnostic::new(
+                        item_name(item),
+                        module_path.clone(),
+                        &err,
+                        type_path.span(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    for path in included {
+        if let Some(attrs) = declaration_attrs(item_at(&mut file.items, &path)) {
+            attrs.push(parse_quote!(#[witgen]));
+        }
+    }
+    diagnostics
+}
+
+/// Struct, enum or type alias of the crate
+#[derive(Clone)]
+struct Declaration {
+    /// Position in the crate, see [`item_at`]
+    path: Vec<usize>,
+    module_path: Vec<String>,
+    annotated: bool,
+}
+
+/// Names a module declares and imports
+#[derive(Default)]
+struct Module {
+    declarations: HashMap<String, Declaration>,
+    /// Inline modules it contains
+    modules: Vec<String>,
+    /// Names imported with `use` along with the path they are imported from
+    imports: Vec<(String, Vec<String>)>,
+    /// Paths of the modules imported with a glob
+    globs: Vec<Vec<String>>,
+}
+
+enum Resolution {
+    Found(Declaration),
+    /// Paths of the types the name could refer to
+    Ambiguous(Vec<String>),
+    /// Imported from another crate
+    External,
+    NotFound,
+}
+
+/// Modules of the crate by module path
+struct Scopes {
+    modules: HashMap<Vec<String>, Module>,
+}
+
+impl Scopes {
+    /// Type `path` refers to in the module `module_path`
+    fn resolve(&self, module_path: &[String], path: &Path) -> Resolution {
+        if path.leading_colon.is_some() {
+            return Resolution::External;
+        }
+        let segments: Vec<String> = path
+            .segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect();
+        if let [name] = segments.as_slice() {
+            return match self.lookup(module_path, name, 0) {
+                Resolution::NotFound => self.find_anywhere(name),
+                res => res,
+            };
+        }
+        match self.absolute(module_path, &segments) {
+            Some(segments) => match segments.split_last() {
+                Some((name, module_path)) => self.lookup(module_path, name, 0),
+                None => Resolution::NotFound,
+            },
+            None => Resolution::External,
+        }
+    }
+
+    /// Type named `name` in the module `module_path`, declared or imported there
+    fn lookup(&self, module_path: &[String], name: &str, depth: usize) -> Resolution {
+        // Guards against `use` cycles
+        if depth > 16 {
+            return Resolution::NotFound;
+        }
+        let module = match self.modules.get(module_path) {
+            Some(module) => module,
+            None => return Resolution::NotFound,
+        };
+        if let Some(declaration) = module.declarations.get(name) {
+            return Resolution::Found(declaration.clone());
+        }
+        if let Some((_, path)) = module.imports.iter().find(|(alias, _)| alias == name) {
+            return match self
+                .absolute(module_path, path)
+                .as_ref()
+                .and_then(|path| path.split_last())
+            {
+                Some((name, module_path)) => self.lookup(module_path, name, depth + 1),
+                None => Resolution::External,
+            };
+        }
+        let mut found: Vec<Declaration> = vec![];
+        for glob in &module.globs {
+            let glob = match self.absolute(module_path, glob) {
+                Some(glob) => glob,
+                None => continue,
+            };
+            match self.lookup(&glob, name, depth + 1) {
+                Resolution::Found(declaration) => {
+                    if !found.iter().any(|found| found.path == declaration.path) {
+                        found.push(declaration);
+                    }
+                }
+                Resolution::Ambiguous(candidates) => return Resolution::Ambiguous(candidates),
+                Resolution::External | Resolution::NotFound => {}
+            }
+        }
+        ambiguity(name, found)
+    }
+
+    /// Type named `name` in any module, when it isn't in scope
+    fn find_anywhere(&self, name: &str) -> Resolution {
+        let mut found: Vec<Declaration> = self
+            .modules
+            .values()
+            .filter_map(|module| module.declarations.get(name).cloned())
+            .collect();
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        ambiguity(name, found)
+    }
+
+    /// Path from the crate root of the item `path` refers to in the module `module_path`, or
+    /// `None` when it is in another crate
+    fn absolute(&self, module_path: &[String], path: &[String]) -> Option<Vec<String>> {
+        let (first, rest) = path.split_first()?;
+        let mut res = match first.as_str() {
+            "crate" => return Some(rest.to_vec()),
+            "self" => module_path.to_vec(),
+            "super" => {
+                let mut res = module_path.to_vec();
+                res.pop()?;
+                let mut rest = rest;
+                while let Some(("super", others)) = rest
+                    .split_first()
+                    .map(|(first, others)| (first.as_str(), others))
+                {
+                    res.pop()?;
+                    rest = others;
+                }
+                res.extend(rest.iter().cloned());
+                return Some(res);
+            }
+            first => {
+                let module = self.modules.get(module_path)?;
+                if module.modules.iter().any(|module| module == first) {
+                    let mut res = module_path.to_vec();
+                    res.push(first.to_string());
+                    res
+                } else {
+                    // A module imported with `use`, e.g. `model::User` after `use crate::model`
+                    let (_, path) = module.imports.iter().find(|(alias, _)| alias == first)?;
+                    self.absolute(module_path, path)?
+                }
+            }
+        };
+        res.extend(rest.iter().cloned());
+        Some(res)
+    }
+}
+
+/// The declaration named `name` if there is a single one
+fn ambiguity(name: &str, mut found: Vec<Declaration>) -> Resolution {
+    match found.len() {
+        0 => Resolution::NotFound,
+        1 => Resolution::Found(found.remove(0)),
+        _ => Resolution::Ambiguous(
+            found
+                .iter()
+                .map(|declaration| {
+                    let mut path = vec!["crate".to_string()];
+                    path.extend(declaration.module_path.iter().cloned());
+                    path.push(name.to_string());
+                    path.join("::")
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Record the declarations, `use` items and inline modules of every module, and the position
+/// of the annotated items to start from along with their module path
+fn collect(
+    items: &[Item],
+    path: &mut Vec<usize>,
+    module_path: &mut Vec<String>,
+    all: bool,
+    modules: &mut HashMap<Vec<String>, Module>,
+    roots: &mut Vec<(Vec<usize>, Vec<String>)>,
+) {
+    let mut module = Module::default();
+    for (index, item) in items.iter().enumerate() {
+        path.push(index);
+        let (ident, attrs) = match item {
+            Item::Struct(item) => (Some(&item.ident), &item.attrs),
+            Item::Enum(item) => (Some(&item.ident), &item.attrs),
+            Item::Type(item) => (Some(&item.ident), &item.attrs),
+            Item::Fn(item) => (None, &item.attrs),
+            Item::Impl(item) => (None, &item.attrs),
+            Item::Trait(item) => (None, &item.attrs),
+            Item::Mod(item) => {
+                if let Some((_, items)) = &item.content {
+                    module.modules.push(item.ident.to_string());
+                    module_path.push(item.ident.to_string());
+                    collect(items, path, module_path, all, modules, roots);
+                    module_path.pop();
+                }
+                path.pop();
+                continue;
+            }
+            Item::Use(item) => {
+                collect_use(&item.tree, &mut vec![], &mut module);
+                path.pop();
+                continue;
+            }
+            _ => {
+                path.pop();
+                continue;
+            }
+        };
+        let annotated = attrs.iter().any(is_witgen_attr);
+        if annotated {
+            // Invalid arguments are reported by the proc macro
+            let deep = WitgenAttrs::from_attrs(attrs).map_or(false, |attrs| attrs.deep);
+            if all || deep {
+                roots.push((path.clone(), module_path.clone()));
+            }
+        }
+        if let Some(ident) = ident {
+            module.declarations.insert(
+                ident.to_string(),
+                Declaration {
+                    path: path.clone(),
+                    module_path: module_path.clone(),
+                    annotated,
+                },
+            );
+        }
+        path.pop();
+    }
+    modules.insert(module_path.clone(), module);
+}
+
+/// Record the names and globs `tree` imports, `prefix` being the path leading to it
+fn collect_use(tree: &UseTree, prefix: &mut Vec<String>, module: &mut Module) {
+    match tree {
+        UseTree::Path(path) => {
+            prefix.push(path.ident.to_string());
+            collect_use(&path.tree, prefix, module);
+            prefix.pop();
+        }
+        UseTree::Name(name) => {
+            let mut path = prefix.clone();
+            path.push(name.ident.to_string());
+            module.imports.push((name.ident.to_string(), path));
+        }
+        UseTree::Rename(rename) => {
+            let mut path = prefix.clone();
+            path.push(rename.ident.to_string());
+            module.imports.push((rename.rename.to_string(), path));
+        }
+        UseTree::Glob(_) => module.globs.push(prefix.clone()),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                collect_use(tree, prefix, module);
+            }
+        }
+    }
+}
+
+fn item_at<'a>(items: &'a mut [Item], path: &[usize]) -> &'a mut Item {
+    let item = &mut items[path[0]];
+    match (item, &path[1..]) {
+        (item, []) => item,
+        (Item::Mod(item_mod), rest) => {
+            let items = &mut item_mod
+                .content
+                .as_mut()
+                .expect("paths only go through inline modules")
+                .1;
+            item_at(items, rest)
+        }
+        _ => unreachable!("paths only go through modules"),
+    }
+}
+
+fn declaration_attrs(item: &mut Item) -> Option<&mut Vec<Attribute>> {
+    match item {
+        Item::Struct(item) => Some(&mut item.attrs),
+        Item::Enum(item) => Some(&mut item.attrs),
+        Item::Type(item) => Some(&mut item.attrs),
+        _ => None,
+    }
+}
+
+/// Name of the Rust item, for diagnostics
+fn item_name(item: &Item) -> String {
+    match item {
+        Item::Struct(item) => item.ident.to_string(),
+        Item::Enum(item) => item.ident.to_string(),
+        Item::Type(item) => item.ident.to_string(),
+        Item::Fn(item) => item.sig.ident.to_string(),
+        Item::Impl(item) => item.self_ty.to_token_stream().to_string(),
+        Item::Trait(item) => item.ident.to_string(),
+        item => item.to_token_stream().to_string(),
+    }
+}
+
+/// Visit the types making up the wit definition of `item`, leaving function bodies aside
+fn visit_types(item: &Item, visitor: &mut TypeNameVisitor) {
+    match item {
+        Item::Fn(item) => visitor.visit_signature_types(&item.sig, &[]),
+        Item::Impl(item) => {
+            for method in item.items.iter().filter_map(pub_method) {
+                visitor.visit_signature_types(&method.sig, &[&item.generics]);
+            }
+        }
+        Item::Trait(item) => {
+            for trait_item in &item.items {
+                if let TraitItem::Method(method) = trait_item {
+                    visitor.visit_signature_types(&method.sig, &[&item.generics]);
+                }
+            }
+        }
+        Item::Struct(item) => {
+            visitor.params = params(&item.generics);
+            visitor.visit_fields(&item.fields);
+        }
+        Item::Enum(item) => {
+            visitor.params = params(&item.generics);
+            for variant in &item.variants {
+                visitor.visit_fields(&variant.fields);
+            }
+        }
+        Item::Type(item) => {
+            visitor.params = params(&item.generics);
+            visitor.visit_type(&item.ty);
+        }
+        _ => {}
+    }
+}
+
+fn params(generics: &Generics) -> Vec<String> {
+    type_params(generics)
+        .into_iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Collects the paths to types, e.g. `Page<User>` and `User` for `Page<User>`, leaving aside the
+/// type parameters in scope and the builtin types
+struct TypeNameVisitor {
+    params: Vec<String>,
+    paths: Vec<Path>,
+}
+
+impl TypeNameVisitor {
+    /// Visit the parameters and result of `sig`, the parameters of `outer` being in scope
+    fn visit_signature_types(&mut self, sig: &Signature, outer: &[&Generics]) {
+        self.params = outer.iter().flat_map(|generics| params(generics)).collect();
+        self.params.extend(params(&sig.generics));
+        for input in &sig.inputs {
+            if let FnArg::Typed(arg) = input {
+                self.visit_type(&arg.ty);
+            }
+        }
+        if let ReturnType::Type(_, ty) = &sig.output {
+            self.visit_type(ty);
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for TypeNameVisitor {
+    fn visit_type_path(&mut self, path: &'ast TypePath) {
+        let is_param = path.qself.is_none()
+            && path.path.segments.len() == 1
+            && self
+                .params
+                .contains(&path.path.segments[0].ident.to_string());
+        let is_builtin = path.path.segments.last().map_or(true, |segment| {
+            BUILTIN_TYPES.contains(&segment.ident.to_string().as_str())
+        });
+        if path.qself.is_none() && !is_param && !is_builtin {
+            self.paths.push(path.path.clone());
+        }
+        visit::visit_type_path(self, path);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use proc_macro2::Span;
+
+/// A Rust item which couldn't be converted to wit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Name of the Rust item
+    pub item: String,
+    /// Modules the item is nested in, starting from the crate root
+    pub module_path: Vec<String>,
+    /// Source file of the item, see [`Diagnostic::locate`]
+    pub file: Option<PathBuf>,
+    /// 1-indexed line
+    pub line: usize,
+    /// 1-indexed column
+    pub column: usize,
+    pub message: String,
+    /// Another location to look at, e.g. the declaration to annotate
+    pub help: Option<Box<Diagnostic>>,
+}
+
+impl Diagnostic {
+    pub fn new(item: String, module_path: Vec<String>, err: &anyhow::Error, span: Span) -> Self {
+        // Errors raised on a specific part of the item carry their own span
+        let span = err
+            .downcast_ref::<syn::Error>()
+            .map_or(span, syn::Error::span);
+        let start = span.start();
+        Self {
+            item,
+            module_path,
+            file: None,
+            line: start.line,
+            column: start.column + 1,
+            message: err.to_string(),
+            help: None,
+        }
+    }
+
+    /// Find the file the item was read from, given the crate's root file
+    ///
+    /// Modules are looked up following the usual `<module>.rs` and `<module>/mod.rs`
+    /// layout, an inline module keeps the file of its parent.
+    pub fn locate(&mut self, root: &Path) {
+        let mut file = root.to_path_buf();
+        let mut dir = root.parent().map(Path::to_path_buf).unwrap_or_default();
+        for module in &self.module_path {
+            let candidates = [
+                dir.join(format!("{module}.rs")),
+                dir.join(module).join("mod.rs"),
+            ];
+            if let Some(found) = candidates.into_iter().find(|path| path.exists()) {
+                file = found;
+            }
+            dir = dir.join(module);
+        }
+        self.file = Some(file);
+        if let Some(help) = &mut self.help {
+            help.locate(root);
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let file = self.file.as_ref().map_or_else(
+            || self.module_path.join("::"),
+            |file| file.display().to_string(),
+        );
+        write!(
+            f,
+            "{file}:{}:{}: `{}`: {}",
+            self.line, self.column, self.item, self.message
+        )?;
+        if let Some(help) = &self.help {
+            write!(f, "\n    help: {help}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every failure met while generating a document
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    /// See [`Diagnostic::locate`]
+    pub fn locate(&mut self, root: &Path) {
+        self.0.iter_mut().for_each(|diag| diag.locate(root));
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} item(s) couldn't be converted to wit:", self.0.len())?;
+        for diag in &self.0 {
+            writeln!(f, "  {diag}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Line and column of an error located at `<file>:<line>:<column>`, as `wit_parser` errors are
+pub(crate) fn error_location(err: &anyhow::Error) -> Option<(usize, usize)> {
+    format!("{err:#}").split_whitespace().find_map(|word| {
+        let word = word.trim_end_matches(|c: char| !c.is_ascii_digit());
+        let mut parts = word.rsplitn(3, ':');
+        let column = parts.next()?.parse().ok()?;
+        let line = parts.next()?.parse().ok()?;
+        parts.next()?;
+        Some((line, column))
+    })
+}
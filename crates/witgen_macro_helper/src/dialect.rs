@@ -0,0 +1,186 @@
+use anyhow::{bail, Context, Result};
+use std::{borrow::Cow, fmt::Display, str::FromStr};
+
+use crate::util::wit_ident;
+
+/// Flavour of WIT emitted by the generators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// 0.2-era WIT understood by `wit-parser` 0.2: `expected<..>`, `float32`, top level `func`s
+    Legacy,
+    /// Component model WIT: `package`, `interface` blocks, `result<..>`, `f32`, `constructor`
+    Component,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::Legacy
+    }
+}
+
+impl Dialect {
+    /// Identifiers which have to be escaped with `%` in this dialect
+    pub fn keywords(&self) -> &'static [&'static str] {
+        match self {
+            Dialect::Legacy => &[
+                "use",
+                "type",
+                "resource",
+                "func",
+                "u8",
+                "u16",
+                "u32",
+                "u64",
+                "s8",
+                "s16",
+                "s32",
+                "s64",
+                "float32",
+                "float64",
+                "char",
+                "handle",
+                "record",
+                "enum",
+                "flags",
+                "variant",
+                "union",
+                "bool",
+                "string",
+                "option",
+                "list",
+                "expected",
+                "future",
+                "stream",
+                "_",
+                "as",
+                "from",
+                "static",
+                "interface",
+                "tuple",
+                "async",
+                "unit",
+            ],
+            Dialect::Component => &[
+                "use",
+                "type",
+                "resource",
+                "func",
+                "u8",
+                "u16",
+                "u32",
+                "u64",
+                "s8",
+                "s16",
+                "s32",
+                "s64",
+                "f32",
+                "f64",
+                "char",
+                "record",
+                "enum",
+                "flags",
+                "variant",
+                "bool",
+                "string",
+                "option",
+                "list",
+                "result",
+                "_",
+                "as",
+                "from",
+                "static",
+                "interface",
+                "tuple",
+                "async",
+                "future",
+                "stream",
+                "world",
+                "import",
+                "export",
+                "package",
+                "constructor",
+                "include",
+                "with",
+                "borrow",
+                "own",
+            ],
+        }
+    }
+
+    pub fn is_keyword(&self, ident: &str) -> bool {
+        self.keywords().contains(&ident)
+    }
+
+    /// `ident` as written in this dialect, e.g. `%type` for a field named `type`
+    pub fn escape<'a>(&self, ident: &'a str) -> Cow<'a, str> {
+        if self.is_keyword(ident) {
+            Cow::Owned(format!("%{ident}"))
+        } else {
+            Cow::Borrowed(ident)
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "legacy" => Ok(Dialect::Legacy),
+            "component" => Ok(Dialect::Component),
+            other => bail!("unknown wit dialect '{other}', expected 'legacy' or 'component'"),
+        }
+    }
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dialect::Legacy => write!(f, "legacy"),
+            Dialect::Component => write!(f, "component"),
+        }
+    }
+}
+
+/// Package declaration of a component model document, e.g. `package my-ns:my-pkg@0.1.0;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageName {
+    pub namespace: String,
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl PackageName {
+    pub fn new(namespace: &str, name: &str, version: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            namespace: wit_ident(namespace),
+            name: wit_ident(name),
+            version: version.map(ToString::to_string),
+        })
+    }
+}
+
+impl FromStr for PackageName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, version) = match s.split_once('@') {
+            Some((id, version)) => (id, Some(version)),
+            None => (s, None),
+        };
+        let (namespace, name) = id
+            .split_once(':')
+            .with_context(|| format!("package '{s}' should look like 'namespace:name@version'"))?;
+        PackageName::new(namespace, name, version)
+    }
+}
+
+impl Display for PackageName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.name)?;
+        if let Some(version) = &self.version {
+            write!(f, "@{version}")?;
+        }
+        Ok(())
+    }
+}
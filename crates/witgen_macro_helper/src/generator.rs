@@ -1,18 +1,28 @@
-use std::fmt::Write;
-
-use anyhow::{bail, Result};
+use anyhow::Result;
+use proc_macro2::Ident;
 use syn::{
-    Attribute, Field, Fields, ItemEnum, ItemFn, ItemImpl, ItemStruct, ItemTrait, ItemType, ItemUse,
-    Lit, Signature, TraitItem, UseGroup, UseName, UsePath, UseRename, UseTree,
+    braced, parse::ParseStream, Attribute, Expr, Field, Fields, GenericParam, Generics, ItemEnum,
+    ItemFn, ItemImpl, ItemMacro, ItemStruct, ItemTrait, ItemType, ItemUse, Lit, ReturnType,
+    Signature, Token, TraitItem, Type, UseName, UsePath, UseRename, UseTree, Visibility,
 };
 
 use crate::{
-    util::{pub_method, wit_ident, FuncType, SignatureUtils},
+    attrs::{is_witgen_attr, wit_name, WitgenAttrs},
+    ir::{
+        Case, Docs, Enum, EnumCase, Field as WitField, Flag, Flags, Function, FunctionKind,
+        Interface, Item, Param, Record, Resource, Type as WitType, TypeAlias, Use,
+        UseName as WitUseName, UseNames, Variant,
+    },
+    util::{
+        non_receiver_args, param_type, pub_method, spanned_error, wit_ident, FuncType,
+        SignatureUtils,
+    },
+    visitor::SelfTypeVisitor,
     wit::ToWitType,
 };
 
 /// Generate a wit record
-/// ```rust
+/// ```rust,ignore
 /// /// Document String
 /// struct FooRecord {
 ///    a: string,
@@ -29,50 +39,78 @@ use crate::{
 /// }
 /// ```
 ///
-pub fn gen_wit_struct(strukt: &ItemStruct) -> Result<String> {
-    if !strukt.generics.params.is_empty() {
-        bail!("doesn't support generic parameters with witgen");
-    }
+pub fn gen_wit_struct(strukt: &ItemStruct) -> Result<Item> {
+    check_generics(&strukt.generics)?;
 
-    let struct_name = wit_ident(&strukt.ident)?;
+    let name = wit_name(&strukt.ident, &strukt.attrs)?;
+    let docs = get_doc_comment(&strukt.attrs, false)?;
     let is_tuple_struct = strukt.fields.iter().any(|f| f.ident.is_none());
-    let fields = gen_fields(strukt.fields.iter().collect::<Vec<&Field>>())?;
-    let fields = if is_tuple_struct {
-        fields.join(", ")
-    } else {
-        fields.join(",\n")
-    };
 
-    let content = if is_tuple_struct {
-        format!("type {} = tuple<{}>\n", struct_name, fields)
+    let item = if WitgenAttrs::from_attrs(&strukt.attrs)?.flags {
+        Item::Flags(Flags {
+            name,
+            docs,
+            flags: gen_flags_fields(&strukt.fields)?,
+        })
+    } else if is_tuple_struct {
+        let types = strukt
+            .fields
+            .iter()
+            .map(|field| field.ty.to_wit())
+            .collect::<Result<Vec<WitType>>>()?;
+        Item::Alias(TypeAlias {
+            name,
+            docs,
+            ty: WitType::Tuple(types),
+        })
     } else {
-        format!(
-            r#"record {} {{
-{}
-}}
-"#,
-            struct_name, fields
-        )
+        Item::Record(Record {
+            name,
+            docs,
+            fields: gen_fields(strukt.fields.iter().collect::<Vec<&Field>>())?,
+        })
     };
-    Ok(content)
+    Ok(item)
 }
 
-fn gen_fields(iter: Vec<&Field>) -> Result<Vec<String>> {
+/// Members of a `#[witgen(flags)]` struct, which must be named `bool` fields
+fn gen_flags_fields(fields: &Fields) -> Result<Vec<Flag>> {
+    fields
+        .iter()
+        .map(|field| {
+            let ident = match &field.ident {
+                Some(ident) => ident,
+                None => return Err(spanned_error(field, "flags fields must be named")),
+            };
+            if !matches!(&field.ty, Type::Path(path) if path.path.is_ident("bool")) {
+                return Err(spanned_error(&field.ty, "flags fields must be `bool`"));
+            }
+            Ok(Flag {
+                name: wit_name(ident, &field.attrs)?,
+                docs: get_doc_comment(&field.attrs, false)?,
+            })
+        })
+        .collect()
+}
+
+fn gen_fields(iter: Vec<&Field>) -> Result<Vec<WitField>> {
     iter.into_iter()
         .map(|field| {
-            let field_name = if let Some(ident) = &field.ident {
-                format!("  {}: ", wit_ident(ident)?)
-            } else {
-                Default::default()
+            let name = match &field.ident {
+                Some(ident) => wit_name(ident, &field.attrs)?,
+                None => return Err(spanned_error(field, "record fields must be named")),
             };
-            let comment = get_doc_comment(&field.attrs, 1, false)?;
-            Ok(format!("{comment}{}{}", field_name, field.ty.to_wit()?))
+            Ok(WitField {
+                name,
+                docs: get_doc_comment(&field.attrs, false)?,
+                ty: field.ty.to_wit()?,
+            })
         })
         .collect()
 }
 
 /// Generate a wit enum
-/// ```rust
+/// ```rust,ignore
 /// /// Top comment
 /// enum MyEnum {
 ///   /// comment case
@@ -89,71 +127,177 @@ fn gen_fields(iter: Vec<&Field>) -> Result<Vec<String>> {
 ///   tuple-variant(tuple<string, s32>),
 /// }
 /// ```
-pub fn gen_wit_enum(enm: &ItemEnum) -> Result<String> {
-    if !enm.generics.params.is_empty() {
-        bail!("doesn't support generic parameters with witgen");
-    }
+///
+/// Variants with named fields get a record of their own, named after the enum and
+/// the variant, which follows the variant in the returned items.
+pub fn gen_wit_enum(enm: &ItemEnum) -> Result<Vec<Item>> {
+    check_generics(&enm.generics)?;
 
-    let enm_name = wit_ident(&enm.ident)?;
+    let name = wit_name(&enm.ident, &enm.attrs)?;
+    let docs = get_doc_comment(&enm.attrs, false)?;
     let is_wit_enum = enm
         .variants
         .iter()
         .all(|v| matches!(v.fields, Fields::Unit));
-    let mut named_types = String::new();
-    let variants = enm
+    if is_wit_enum {
+        let cases = enm
+            .variants
+            .iter()
+            .map(|variant| {
+                Ok(EnumCase {
+                    name: wit_name(&variant.ident, &variant.attrs)?,
+                    docs: get_doc_comment(&variant.attrs, false)?,
+                })
+            })
+            .collect::<Result<Vec<EnumCase>>>()?;
+        return Ok(vec![Item::Enum(Enum { name, docs, cases })]);
+    }
+
+    let mut named_types = vec![];
+    let cases = enm
         .variants
         .iter()
         .map(|variant| {
-            let ident = wit_ident(&variant.ident)?;
-            let comment = get_doc_comment(&variant.attrs, 1, false)?;
-            let variant_string = match &variant.fields {
+            let ident = wit_name(&variant.ident, &variant.attrs)?;
+            let docs = get_doc_comment(&variant.attrs, false)?;
+            let ty = match &variant.fields {
                 syn::Fields::Named(named) => {
-                    let fields = gen_fields(named.named.iter().collect())?.join(",\n");
-                    let inner_type_name = &format!("{}-{}", enm_name, ident);
-                    let comment = get_doc_comment(&variant.attrs, 0, false)?;
-                    write!(
-                        &mut named_types,
-                        "{}record {} {{\n{}\n}}\n",
-                        comment, inner_type_name, fields
-                    )?;
-                    Ok(format!("{}({})", ident, inner_type_name))
+                    let inner_type_name = format!("{}-{}", name, ident);
+                    named_types.push(Item::Record(Record {
+                        name: inner_type_name.clone(),
+                        docs: docs.clone(),
+                        fields: gen_fields(named.named.iter().collect())?,
+                    }));
+                    Some(WitType::Named(inner_type_name))
                 }
                 syn::Fields::Unnamed(unamed) => {
-                    let fields = unamed
+                    let mut fields = unamed
                         .unnamed
                         .iter()
                         .map(|field| field.ty.to_wit())
-                        .collect::<Result<Vec<String>>>()?
-                        .join(", ");
+                        .collect::<Result<Vec<WitType>>>()?;
 
-                    let formatted_field = if unamed.unnamed.len() > 1 {
-                        format!("tuple<{}>", fields)
+                    if fields.len() > 1 {
+                        Some(WitType::Tuple(fields))
                     } else {
-                        fields
-                    };
-
-                    Ok(format!("{}({})", ident, formatted_field))
+                        fields.pop()
+                    }
                 }
-                syn::Fields::Unit => Ok(ident),
+                syn::Fields::Unit => None,
             };
-            variant_string.map(|v| format!("{}  {},", comment, v))
+            Ok(Case {
+                name: ident,
+                docs,
+                ty,
+            })
         })
-        .collect::<Result<Vec<String>>>()?
-        .join("\n");
-    let ty = if is_wit_enum { "enum" } else { "variant" };
-    let content = format!(
-        r#"{} {} {{
-{}
-}}
-"#,
-        ty, enm_name, variants
-    );
+        .collect::<Result<Vec<Case>>>()?;
 
-    Ok(format!("{}{}", content, named_types))
+    let mut items = vec![Item::Variant(Variant { name, docs, cases })];
+    items.extend(named_types);
+    Ok(items)
+}
+
+/// Generate wit flags from a `bitflags!` invocation
+/// ```rust,ignore
+/// bitflags! {
+///     /// Permissions of a file
+///     #[witgen]
+///     pub struct Permissions: u8 {
+///         /// Can be read
+///         const READ = 0b01;
+///         const WRITE_ONCE = 0b10;
+///     }
+/// }
+/// ```
+/// becomes
+/// ```ts
+/// /// Permissions of a file
+/// flags permissions {
+///   /// Can be read
+///   read,
+///   write-once,
+/// }
+/// ```
+///
+/// Every struct of the invocation is generated when `#[witgen]` annotates the macro itself,
+/// otherwise only the structs annotated with `#[witgen]` are.
+pub fn gen_wit_bitflags(mac: &ItemMacro) -> Result<Vec<Item>> {
+    let all = mac.attrs.iter().any(is_witgen_attr);
+    parse_bitflags(mac)?
+        .into_iter()
+        .filter(|strukt| all || strukt.attrs.iter().any(is_witgen_attr))
+        .map(|strukt| {
+            let flags = strukt
+                .flags
+                .iter()
+                .map(|(attrs, ident)| {
+                    Ok(Flag {
+                        name: wit_name(ident, attrs)?,
+                        docs: get_doc_comment(attrs, false)?,
+                    })
+                })
+                .collect::<Result<Vec<Flag>>>()?;
+            Ok(Item::Flags(Flags {
+                name: wit_name(&strukt.ident, &strukt.attrs)?,
+                docs: get_doc_comment(&strukt.attrs, false)?,
+                flags,
+            }))
+        })
+        .collect()
+}
+
+/// `bitflags!` or `bitflags::bitflags!`
+pub(crate) fn is_bitflags(mac: &ItemMacro) -> bool {
+    mac.mac
+        .path
+        .segments
+        .last()
+        .map_or(false, |segment| segment.ident == "bitflags")
+}
+
+/// A struct declared in a `bitflags!` invocation
+pub(crate) struct BitflagsStruct {
+    pub attrs: Vec<Attribute>,
+    pub ident: Ident,
+    /// Attributes and name of each `const`
+    pub flags: Vec<(Vec<Attribute>, Ident)>,
+}
+
+pub(crate) fn parse_bitflags(mac: &ItemMacro) -> Result<Vec<BitflagsStruct>> {
+    Ok(mac.mac.parse_body_with(|input: ParseStream| {
+        let mut structs = vec![];
+        while !input.is_empty() {
+            let attrs = input.call(Attribute::parse_outer)?;
+            input.parse::<Visibility>()?;
+            input.parse::<Token![struct]>()?;
+            let ident = input.parse()?;
+            input.parse::<Token![:]>()?;
+            input.parse::<Type>()?;
+            let content;
+            braced!(content in input);
+            let mut flags = vec![];
+            while !content.is_empty() {
+                let attrs = content.call(Attribute::parse_outer)?;
+                content.parse::<Token![const]>()?;
+                let ident = content.parse()?;
+                content.parse::<Token![=]>()?;
+                content.parse::<Expr>()?;
+                content.parse::<Token![;]>()?;
+                flags.push((attrs, ident));
+            }
+            structs.push(BitflagsStruct {
+                attrs,
+                ident,
+                flags,
+            });
+        }
+        Ok(structs)
+    })?)
 }
 
 /// Generate a wit function
-/// ```rust
+/// ```rust,ignore
 /// /// Document String
 /// fn foo(a: string, b: Option<i32>) -> Result<string> { Ok(a)}
 /// ```
@@ -162,30 +306,35 @@ pub fn gen_wit_enum(enm: &ItemEnum) -> Result<String> {
 /// /// Document String
 /// foo: function(a: string, b: option<s32>) -> expected<string>
 /// ```
+/// or in the component dialect
+/// ```ts
+/// /// Document String
+/// foo: func(a: string, b: option<s32>) -> result<string>;
+/// ```
 ///
-pub fn gen_wit_function(func: &ItemFn) -> Result<String> {
-    let signature = &func.sig;
-    gen_wit_function_from_signature(signature)
+pub fn gen_wit_function(func: &ItemFn) -> Result<Function> {
+    let mut function = gen_wit_function_from_signature(&func.sig, &func.attrs)?;
+    function.docs = get_doc_comment(&func.attrs, false)?;
+    Ok(function)
 }
 
-fn gen_wit_function_from_signature(signature: &Signature) -> Result<String> {
-    let fn_name = wit_ident(&signature.ident)?;
-    let fn_type = signature.fn_type();
-    let fn_args = signature.fn_args()?.join(", ");
-    let ret = signature.ret_args()?;
-
-    let preamble = if let FuncType::Instance(true) = fn_type {
-        "///@mutable\n  "
-    } else {
-        ""
-    }
-    .to_string();
-
-    Ok(format!("{preamble}{fn_name}: func({fn_args}){ret}\n"))
+fn gen_wit_function_from_signature(signature: &Signature, attrs: &[Attribute]) -> Result<Function> {
+    let kind = match signature.fn_type() {
+        FuncType::Instance(mutable) => FunctionKind::Method { mutable },
+        FuncType::Consuming => FunctionKind::Method { mutable: false },
+        FuncType::Standalone => FunctionKind::Freestanding,
+    };
+    Ok(Function {
+        name: wit_name(&signature.ident, attrs)?,
+        docs: vec![],
+        kind,
+        params: signature.fn_args()?,
+        result: signature.ret_args()?,
+    })
 }
 
 /// Generate a wit type alias
-/// ```rust
+/// ```rust,ignore
 /// /// Document String
 /// type foo = (String, option<bool>);
 /// ```
@@ -195,33 +344,44 @@ fn gen_wit_function_from_signature(signature: &Signature) -> Result<String> {
 /// type foo = tuple<string, option<bool>>
 /// ```
 ///
-pub fn gen_wit_type_alias(type_alias: &ItemType) -> Result<String> {
-    if !type_alias.generics.params.is_empty() {
-        bail!("doesn't support generic parameters with witgen");
+pub fn gen_wit_type_alias(type_alias: &ItemType) -> Result<TypeAlias> {
+    check_generics(&type_alias.generics)?;
+    Ok(TypeAlias {
+        name: wit_name(&type_alias.ident, &type_alias.attrs)?,
+        docs: get_doc_comment(&type_alias.attrs, false)?,
+        ty: type_alias.ty.to_wit()?,
+    })
+}
+
+/// Lifetimes are fine since references are transparent and type parameters are replaced
+/// when the item is instantiated, see [`crate::generics`]
+fn check_generics(generics: &Generics) -> Result<()> {
+    match generics
+        .params
+        .iter()
+        .find(|param| matches!(param, GenericParam::Const(_)))
+    {
+        Some(param) => Err(spanned_error(
+            param,
+            "doesn't support const generic parameters with witgen",
+        )),
+        None => Ok(()),
     }
-    let ty = type_alias.ty.to_wit()?;
-    let type_alias_ident = wit_ident(&type_alias.ident)?;
-    Ok(format!("type {} = {}\n", type_alias_ident, ty))
 }
 
-pub(crate) fn get_doc_comment(
-    attrs: &[Attribute],
-    depth: usize,
-    include_paths: bool,
-) -> Result<String> {
-    let mut comment = String::new();
-    let spaces = " ".repeat(depth * 2);
+/// Doc comment lines of `attrs`, optionally with path attributes such as `#[payable]` as `@payable`
+pub(crate) fn get_doc_comment(attrs: &[Attribute], include_paths: bool) -> Result<Docs> {
+    let mut comment = vec![];
     for attr in attrs {
         match &attr.parse_meta()? {
             syn::Meta::NameValue(name_val) if name_val.path.is_ident("doc") => {
                 if let Lit::Str(lit_str) = &name_val.lit {
-                    let text = lit_str.value();
-                    writeln!(&mut comment, "{spaces}///{text}",)?;
+                    comment.push(lit_str.value());
                 }
             }
             syn::Meta::Path(path) if include_paths => {
                 if let Some(ident) = path.get_ident() {
-                    writeln!(&mut comment, "{spaces}///@{ident}")?;
+                    comment.push(format!("@{ident}"));
                 }
             }
             _ => {}
@@ -230,90 +390,267 @@ pub(crate) fn get_doc_comment(
     Ok(comment)
 }
 
-#[allow(unused_variables, unreachable_code)]
-fn gen_use_names(use_tree: &UseTree) -> Result<String> {
-    let res = match use_tree {
-        UseTree::Path(_) => {
-            todo!("Can only have top level path, e.g. cannot do `use other_crate::module::...`")
+/// Names imported by `use_tree` from a crate
+///
+/// A dependency generates a single wit file, so module paths such as `dep::module::Item`
+/// import `item` from `dep`.
+fn gen_use_names(use_tree: &UseTree, names: &mut Vec<WitUseName>, glob: &mut bool) -> Result<()> {
+    match use_tree {
+        UseTree::Glob(_) => *glob = true,
+        UseTree::Path(UsePath { tree, .. }) => gen_use_names(tree, names, glob)?,
+        UseTree::Name(UseName { ident }) if ident == "self" => {
+            return Err(spanned_error(ident, "can only import types and functions"))
         }
-        UseTree::Name(UseName { ident }) => {
-          todo!("Cannot specify one import must be *. e.g. `use other_crate::Import` --> `use other_crate::*`");
-          wit_ident(&ident)?
-        },
+        UseTree::Name(UseName { ident }) => names.push(WitUseName {
+            name: wit_ident(ident),
+            rename: None,
+        }),
         UseTree::Rename(UseRename { ident, rename, .. }) => {
-            // TODO: Currently imported types aren't renamed when generated so this is still a todo.
-            todo!("Cannot have renamed imports yet. E.g. `use other_crate::Import as OtherImport`");
-            let (ident, rename) = (wit_ident(ident)?, (wit_ident(rename)?));
-            format!("{ident} as {rename}")
+            let name = wit_ident(ident);
+            let rename = wit_ident(rename);
+            names.push(WitUseName {
+                rename: if rename != name { Some(rename) } else { None },
+                name,
+            })
         }
-        UseTree::Glob(_) => return Ok("*".to_string()),
-        UseTree::Group(UseGroup { items, .. }) => {
-          todo!("Cannot specify group yet. E.g. `use other_crate::{{Import1, Import2}}`");
-          items
-            .iter()
-            .map(gen_use_names)
-            .collect::<Result<Vec<String>>>()?
-            .join(",")},
-    };
-    Ok(format!("{{{res}}}"))
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                gen_use_names(tree, names, glob)?;
+            }
+        }
+    }
+    Ok(())
 }
 
-pub fn gen_wit_import(import: &ItemUse) -> Result<String> {
-    let (id, use_names) = match &import.tree {
-        UseTree::Path(UsePath { ident, tree, ..  }) => {
-          (wit_ident(ident)?, gen_use_names(tree)?)
+/// Generate the wit `use` statements of a `use` item, one for each crate it imports from
+/// ```rust,ignore
+/// use dep::{Item, module::Other as Renamed};
+/// ```
+/// becomes
+/// ```ts
+/// use {item, other as renamed} from dep
+/// ```
+///
+/// Globs, e.g. `use dep::module::*`, import everything generated by the crate. Items renamed
+/// with `as` get a `use` of their own in that case.
+pub fn gen_wit_import(import: &ItemUse) -> Result<Vec<Use>> {
+    let docs = get_doc_comment(&import.attrs, false)?;
+    let mut crates = vec![];
+    flatten_use_roots(&import.tree, &mut crates)?;
+    let mut uses: Vec<Use> = vec![];
+    for (ident, tree) in crates {
+        let (mut names, mut glob) = (vec![], false);
+        gen_use_names(tree, &mut names, &mut glob)?;
+        let from = wit_ident(ident);
+        if glob {
+            names.retain(|name| name.rename.is_some());
+            uses.push(Use {
+                docs: docs.clone(),
+                from: from.clone(),
+                names: UseNames::Glob,
+            });
         }
-          ,
-        _ => todo!("Can only use top level 'path', e.g. `use import_crate::*` -> `import_crate`. More specific imports is a TODO."),
-    };
-    // Todo allow referencing specific items
-    let res = format!("use {use_names} from {id}");
-    println!("{res}");
-    Ok(res)
+        if !names.is_empty() {
+            uses.push(Use {
+                docs: docs.clone(),
+                from,
+                names: UseNames::Names(names),
+            });
+        }
+    }
+    Ok(uses)
+}
+
+/// Crates imported from by `use_tree`, e.g. `dep` and `other` for `use {dep::A, other::B}`
+fn flatten_use_roots<'a>(
+    use_tree: &'a UseTree,
+    crates: &mut Vec<(&'a Ident, &'a UseTree)>,
+) -> Result<()> {
+    match use_tree {
+        UseTree::Path(UsePath { ident, .. })
+            if ident == "crate" || ident == "self" || ident == "super" =>
+        {
+            Err(spanned_error(
+                ident,
+                "can only import from other crates, items of this crate are already generated",
+            ))
+        }
+        UseTree::Path(UsePath { ident, tree, .. }) => {
+            crates.push((ident, tree));
+            Ok(())
+        }
+        UseTree::Group(group) => group
+            .items
+            .iter()
+            .try_for_each(|tree| flatten_use_roots(tree, crates)),
+        other => Err(spanned_error(
+            other,
+            "expected a path starting with a crate name, e.g. `use other_crate::Import`",
+        )),
+    }
 }
 
-pub fn gen_wit_trait(trait_: &ItemTrait) -> Result<String> {
-    let name = wit_ident(&trait_.ident)?;
-    let mut res = format!("interface {name} {{\n");
+pub fn gen_wit_trait(trait_: &ItemTrait) -> Result<Interface> {
+    let mut items = vec![];
 
     for item in trait_.items.iter() {
         match item {
-            TraitItem::Const(_) => todo!("Const in Trait isn't implemented yet"),
+            TraitItem::Const(item) => {
+                return Err(spanned_error(item, "Const in Trait isn't implemented yet"))
+            }
             TraitItem::Method(method) => {
-                let comment = get_doc_comment(&method.attrs, 1, true)?;
-                write!(
-                    &mut res,
-                    "{comment}  {}",
-                    gen_wit_function_from_signature(&method.sig)?
-                )?
+                let mut func = gen_wit_function_from_signature(&method.sig, &method.attrs)?;
+                func.docs = get_doc_comment(&method.attrs, true)?;
+                items.push(Item::Function(func));
+            }
+            TraitItem::Type(item) => {
+                return Err(spanned_error(item, "Type in Trait isn't implemented yet"))
+            }
+            TraitItem::Macro(item) => {
+                return Err(spanned_error(item, "Macro in Trait isn't implemented yet"))
             }
-            TraitItem::Type(_) => todo!("Type in Trait isn't implemented yet"),
-            TraitItem::Macro(_) => todo!("Macro in Trait isn't implemented yet"),
-            TraitItem::Verbatim(_) => todo!("Verbatim in Trait isn't implemented yet"),
-            _ => todo!("extra case in Trait isn't implemented yet"),
+            other => {
+                return Err(spanned_error(
+                    other,
+                    "this kind of item in Trait isn't implemented yet",
+                ))
+            }
+        }
+    }
+    Ok(Interface {
+        name: wit_name(&trait_.ident, &trait_.attrs)?,
+        docs: get_doc_comment(&trait_.attrs, false)?,
+        items,
+    })
+}
+
+/// Generate a wit resource
+/// ```rust,ignore
+/// impl Counter {
+///     pub fn new(start: u32) -> Self { todo!() }
+///     pub fn get(&self) -> u32 { todo!() }
+///     pub fn merge(&mut self, other: &Self) { todo!() }
+///     pub fn zero() -> u32 { 0 }
+///     pub fn finish(self) -> u32 { todo!() }
+/// }
+/// ```
+/// becomes, in the component dialect
+/// ```ts
+/// resource counter {
+///   constructor(start: u32);
+///   get: func() -> u32;
+///   ///@mutable
+///   merge: func(other: borrow<counter>);
+///   zero: static func() -> u32;
+///   finish: static func(self: own<counter>) -> u32;
+/// }
+/// ```
+pub fn gen_wit_impl(impl_: &ItemImpl) -> Result<Resource> {
+    let name = match impl_.self_ty.to_wit()? {
+        WitType::Named(name) => WitgenAttrs::from_attrs(&impl_.attrs)?
+            .rename
+            .unwrap_or(name),
+        _ => {
+            return Err(spanned_error(
+                &impl_.self_ty,
+                "resources can only be implemented on named types",
+            ))
         }
+    };
+    let functions = impl_
+        .items
+        .iter()
+        .filter_map(pub_method)
+        .map(|method| {
+            let sig = SelfTypeVisitor::replace_self(&method.sig, &impl_.self_ty);
+            let constructor = WitgenAttrs::from_attrs(&method.attrs)?.constructor;
+            let mut params = gen_resource_args(&sig, &impl_.self_ty, &name)?;
+            let kind = match sig.fn_type() {
+                FuncType::Standalone
+                    if constructor && !returns_self(&method.sig, &impl_.self_ty) =>
+                {
+                    return Err(spanned_error(
+                        &method.sig,
+                        "a constructor has to return `Self`",
+                    ))
+                }
+                FuncType::Standalone if constructor || is_constructor(&method.sig) => {
+                    FunctionKind::Constructor
+                }
+                FuncType::Standalone => FunctionKind::Static,
+                _ if constructor => {
+                    return Err(spanned_error(
+                        &method.sig,
+                        "a constructor can't take `self`",
+                    ))
+                }
+                FuncType::Instance(mutable) => FunctionKind::Method { mutable },
+                FuncType::Consuming => {
+                    params.insert(
+                        0,
+                        Param {
+                            name: "self".to_string(),
+                            ty: WitType::Own(name.clone()),
+                        },
+                    );
+                    FunctionKind::Consumer
+                }
+            };
+            Ok(Function {
+                name: wit_name(&sig.ident, &method.attrs)?,
+                docs: get_doc_comment(&method.attrs, true)?,
+                kind,
+                params,
+                result: sig.ret_args()?,
+            })
+        })
+        .collect::<Result<Vec<Function>>>()?;
+    Ok(Resource {
+        name,
+        docs: get_doc_comment(&impl_.attrs, false)?,
+        functions,
+    })
+}
+
+/// Arguments of a resource method, where references to the resource itself, and to other
+/// resources, are borrowed handles
+fn gen_resource_args(sig: &Signature, self_ty: &Type, name: &str) -> Result<Vec<Param>> {
+    sig.inputs
+        .iter()
+        .filter_map(non_receiver_args)
+        .map(|typed_pat| {
+            let pat = match &*typed_pat.pat {
+                syn::Pat::Ident(ident) => wit_name(&ident.ident, &typed_pat.attrs)?,
+                pat => return Err(spanned_error(pat, "can't handle this kind of fn argument")),
+            };
+            let ty = match typed_pat.ty.as_ref() {
+                Type::Reference(reference) if reference.elem.as_ref() == self_ty => {
+                    WitType::Borrow(name.to_string())
+                }
+                ty => param_type(ty)?,
+            };
+            Ok(Param { name: pat, ty })
+        })
+        .collect()
+}
+
+/// `fn new(..) -> Self` is the resource's constructor
+/// Whether `sig` returns `Self` or `self_ty`, the type the method is implemented on
+fn returns_self(sig: &Signature, self_ty: &Type) -> bool {
+    match &sig.output {
+        ReturnType::Type(_, ty) => match ty.as_ref() {
+            Type::Path(path) if path.path.is_ident("Self") => true,
+            ty => ty == self_ty,
+        },
+        ReturnType::Default => false,
     }
-    res.push_str("}\n");
-    Ok(res)
 }
 
-pub fn gen_wit_impl(impl_: &ItemImpl) -> Result<String> {
-    let name = wit_ident(&impl_.self_ty.to_wit()?)?;
-    let mut res = format!("resource {name} {{\n");
-    for method in impl_.items.iter().filter_map(pub_method) {
-        let comment = get_doc_comment(&method.attrs, 1, true)?;
-        let static_decl = if matches!(method.sig.fn_type(), FuncType::Standalone) {
-            "static "
-        } else {
-            ""
+fn is_constructor(sig: &Signature) -> bool {
+    match &sig.output {
+        ReturnType::Type(_, ty) if sig.ident == "new" => {
+            matches!(ty.as_ref(), Type::Path(path) if path.path.is_ident("Self"))
         }
-        .to_string();
-        write!(
-            &mut res,
-            "{comment}  {static_decl}{}",
-            gen_wit_function_from_signature(&method.sig)?
-        )?
+        _ => false,
     }
-    res.push_str("}\n");
-    Ok(res)
 }
@@ -0,0 +1,257 @@
+//! Monomorphization of generic structs, enums and type aliases.
+//!
+//! A generic item isn't generated on its own. Every concrete instantiation found in the
+//! other items, e.g. `Page<User>`, becomes a specialised definition named after the item and
+//! its arguments, e.g. `page-user`.
+
+use anyhow::{bail, Result};
+use proc_macro2::Ident;
+use std::collections::{HashMap, HashSet};
+use syn::{
+    visit::{self, Visit},
+    GenericArgument, GenericParam, Generics, PathArguments, Type as SynType, TypePath,
+};
+
+use crate::{
+    attrs::wit_name,
+    diagnostics::{Diagnostic, Diagnostics},
+    ir::{Document, Type},
+    util::wit_ident,
+    visitor::TypeParamVisitor,
+    wit::{ToWitType, Wit},
+};
+
+/// Name of the specialised definition of a generic type
+/// ```ts
+/// page<user> -> page-user
+/// pair<string, list<u8>> -> pair-string-list-u8
+/// ```
+pub fn instance_name(base: &str, args: &[Type]) -> String {
+    let mut name = base.to_string();
+    for arg in args {
+        name.push('-');
+        name.push_str(&mangle(arg));
+    }
+    name
+}
+
+fn mangle(ty: &Type) -> String {
+    match ty {
+        Type::Bool => "bool".to_string(),
+        Type::U8 => "u8".to_string(),
+        Type::U16 => "u16".to_string(),
+        Type::U32 => "u32".to_string(),
+        Type::U64 => "u64".to_string(),
+        Type::S8 => "s8".to_string(),
+        Type::S16 => "s16".to_string(),
+        Type::S32 => "s32".to_string(),
+        Type::S64 => "s64".to_string(),
+        Type::F32 => "f32".to_string(),
+        Type::F64 => "f64".to_string(),
+        Type::Char => "char".to_string(),
+        Type::String => "string".to_string(),
+        Type::List(ty) => format!("list-{}", mangle(ty)),
+        Type::Option(ty) => format!("option-{}", mangle(ty)),
+        Type::Result { ok, err } => {
+            let mut res = format!("result-{}", mangle(ok));
+            if let Some(err) = err {
+                res.push('-');
+                res.push_str(&mangle(err));
+            }
+            res
+        }
+        Type::Tuple(types) if types.is_empty() => "unit".to_string(),
+        Type::Tuple(types) => instance_name("tuple", types),
+        Type::Named(name) | Type::Borrow(name) | Type::Own(name) => name.clone(),
+    }
+}
+
+/// Type arguments of a path such as `Page<'a, User>`, if any
+pub(crate) fn type_args(path: &TypePath) -> Option<Vec<&SynType>> {
+    match &path.path.segments.last()?.arguments {
+        PathArguments::AngleBracketed(args) => Some(
+            args.args
+                .iter()
+                .filter_map(|arg| match arg {
+                    GenericArgument::Type(ty) => Some(ty),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Type parameters of a generic item, lifetimes aside
+pub(crate) fn type_params(generics: &Generics) -> Vec<&Ident> {
+    generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(&param.ident),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collects the generic items of a document and their instantiations
+#[derive(Default)]
+pub(crate) struct Monomorphizer<'a> {
+    /// Generic items by Rust name, along with the module they are declared in
+    templates: HashMap<String, (Vec<String>, &'a Wit)>,
+    /// Rust name and type arguments of each instantiation found so far
+    instances: Vec<(String, Vec<SynType>)>,
+    /// Instances named with `#[witgen(name = "...")]`
+    names: Vec<(String, String)>,
+}
+
+impl<'a> Monomorphizer<'a> {
+    pub fn add_template(&mut self, module_path: &[String], template: &'a Wit) {
+        self.templates
+            .insert(template.name(), (module_path.to_vec(), template));
+    }
+
+    /// Name the instantiation `ty` refers to, e.g. `type Users = Page<User>`
+    pub fn add_name(&mut self, ty: &SynType, name: String) -> Result<()> {
+        match ty.to_wit()? {
+            Type::Named(instance) => self.names.push((instance, name)),
+            _ => bail!("`name` can only be given to an instantiation of a generic type"),
+        }
+        Ok(())
+    }
+
+    /// Record the instantiations used by the definitions of `item`
+    pub fn visit(&mut self, item: &Wit) {
+        let mut visitor = InstanceVisitor { found: vec![] };
+        item.visit_types(&mut visitor);
+        self.instances.extend(visitor.found);
+    }
+
+    /// Add the specialised definition of every instantiation to `doc`
+    pub fn instantiate(mut self, doc: &mut Document, diagnostics: &mut Diagnostics) {
+        let mut done = HashSet::new();
+        let mut renames = vec![];
+        let mut next = 0;
+        while next < self.instances.len() {
+            let (ident, args) = self.instances[next].clone();
+            next += 1;
+            let (module_path, template) = match self.templates.get(&ident) {
+                Some(template) => template.clone(),
+                None => continue,
+            };
+            let instance = match instantiate(template, &args) {
+                Ok(instance) => instance,
+                Err(err) => {
+                    diagnostics.push(Diagnostic::new(
+                        template.name(),
+                        module_path,
+                        &err,
+                        template.span(),
+                    ));
+                    continue;
+                }
+            };
+            if !done.insert(instance.name.clone()) {
+                continue;
+            }
+            if instance.name != instance.reference {
+                renames.push((instance.reference.clone(), instance.name.clone()));
+            }
+            match instance.wit.to_items() {
+                Ok(items) => {
+                    let mut instance_doc = Document::new(items);
+                    // Records generated for enum variants are prefixed with the enum name
+                    let names: Vec<String> = instance_doc
+                        .type_names()
+                        .into_iter()
+                        .map(ToString::to_string)
+                        .collect();
+                    for name in names {
+                        if let Some(suffix) = name.strip_prefix(&instance.base) {
+                            instance_doc.rename(&name, &format!("{}{suffix}", instance.name));
+                        }
+                    }
+                    doc.merge(instance_doc);
+                }
+                Err(err) => diagnostics.push(Diagnostic::new(
+                    template.name(),
+                    module_path,
+                    &err,
+                    template.span(),
+                )),
+            }
+            self.visit(&instance.wit);
+        }
+        for (from, to) in renames.iter().chain(&self.names) {
+            doc.rename(from, to);
+        }
+    }
+}
+
+struct Instance {
+    wit: Wit,
+    /// wit name of the generic item
+    base: String,
+    /// wit name of the specialised definition
+    name: String,
+    /// Name the references to this instantiation use, see `ToWitType`
+    reference: String,
+}
+
+/// Replace the type parameters of `template` by `args`
+fn instantiate(template: &Wit, args: &[SynType]) -> Result<Instance> {
+    let (ident, attrs, generics) = match template {
+        Wit::Record(item) => (&item.ident, &item.attrs, &item.generics),
+        Wit::Variant(item) => (&item.ident, &item.attrs, &item.generics),
+        Wit::Type(item) => (&item.ident, &item.attrs, &item.generics),
+        _ => bail!("only structs, enums and type aliases can be generic"),
+    };
+    let params = type_params(generics);
+    if params.len() != args.len() {
+        bail!(
+            "expected {} generic arguments, found {}",
+            params.len(),
+            args.len()
+        );
+    }
+    let wit_args = args
+        .iter()
+        .map(ToWitType::to_wit)
+        .collect::<Result<Vec<Type>>>()?;
+    let base = wit_name(ident, attrs)?;
+    let name = instance_name(&base, &wit_args);
+    let reference = instance_name(&wit_ident(ident), &wit_args);
+
+    let mut visitor = TypeParamVisitor::new(params.into_iter().cloned().zip(args.to_vec()));
+    let wit = match template {
+        Wit::Record(item) => Wit::Record(visitor.substitute_struct(item)),
+        Wit::Variant(item) => Wit::Variant(visitor.substitute_enum(item)),
+        Wit::Type(item) => Wit::Type(visitor.substitute_type(item)),
+        _ => unreachable!(),
+    };
+    Ok(Instance {
+        wit,
+        base,
+        name,
+        reference,
+    })
+}
+
+/// Finds the paths with type arguments, e.g. `Page<User>`
+struct InstanceVisitor {
+    found: Vec<(String, Vec<SynType>)>,
+}
+
+impl<'ast> Visit<'ast> for InstanceVisitor {
+    fn visit_type_path(&mut self, path: &'ast TypePath) {
+        if let (Some(segment), Some(args)) = (path.path.segments.last(), type_args(path)) {
+            if !args.is_empty() {
+                self.found.push((
+                    segment.ident.to_string(),
+                    args.into_iter().cloned().collect(),
+                ));
+            }
+        }
+        visit::visit_type_path(self, path);
+    }
+}
@@ -0,0 +1,1291 @@
+//! Typed intermediate representation of wit definitions.
+//!
+//! The generators turn `syn` items into these types and [`crate::printer::Printer`]
+//! renders them, so definitions can be inspected and transformed before printing.
+
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+use wit_parser::{FunctionKind as ParsedFunctionKind, Type as ParsedType, TypeDefKind};
+
+use crate::PackageName;
+
+/// Doc comment lines, without the leading `///`
+pub type Docs = Vec<String>;
+
+/// A whole wit file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Document {
+    pub package: Option<PackageName>,
+    /// Name of the interface the items are declared in with the component dialect, the name of
+    /// the package by default
+    pub name: Option<String>,
+    /// Docs of that interface
+    pub docs: Docs,
+    pub items: Vec<Item>,
+    /// Worlds of the component dialect, importing and exporting items of the document
+    pub worlds: Vec<World>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Use(Use),
+    Record(Record),
+    Variant(Variant),
+    Enum(Enum),
+    Flags(Flags),
+    Alias(TypeAlias),
+    Function(Function),
+    Resource(Resource),
+    Interface(Interface),
+}
+
+/// `use * from dep` or `use {a, b as c} from dep`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Use {
+    pub docs: Docs,
+    pub from: String,
+    pub names: UseNames,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UseNames {
+    Glob,
+    Names(Vec<UseName>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UseName {
+    pub name: String,
+    pub rename: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub docs: Docs,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub docs: Docs,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub name: String,
+    pub docs: Docs,
+    pub cases: Vec<Case>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub docs: Docs,
+    pub ty: Option<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Enum {
+    pub name: String,
+    pub docs: Docs,
+    pub cases: Vec<EnumCase>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumCase {
+    pub name: String,
+    pub docs: Docs,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flags {
+    pub name: String,
+    pub docs: Docs,
+    pub flags: Vec<Flag>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flag {
+    pub name: String,
+    pub docs: Docs,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeAlias {
+    pub name: String,
+    pub docs: Docs,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub docs: Docs,
+    pub kind: FunctionKind,
+    pub params: Vec<Param>,
+    /// `None` when the function returns nothing, `Some(Type::Tuple(vec![]))` for `-> ()`
+    pub result: Option<Type>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    Freestanding,
+    /// Takes `&self` or `&mut self`
+    Method {
+        mutable: bool,
+    },
+    Static,
+    /// `fn new(..) -> Self` of a resource, or a function annotated with
+    /// `#[witgen(constructor)]`
+    Constructor,
+    /// Takes `self` by value, consuming the resource: a static function whose first parameter,
+    /// `self`, is the owned handle
+    Consumer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    pub name: String,
+    pub docs: Docs,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub docs: Docs,
+    pub items: Vec<Item>,
+}
+
+/// `world` declaration of the component dialect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct World {
+    /// `None` for the default world, named after the package
+    pub name: Option<String>,
+    pub imports: Vec<WorldItem>,
+    pub exports: Vec<WorldItem>,
+}
+
+/// Item of the document imported into or exported from a world
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorldItem {
+    /// Interface of the given name, `None` standing for the interface of the document itself
+    Interface(Option<String>),
+    /// Function declared by the document, or by the interface of the given name
+    Function {
+        interface: Option<String>,
+        name: String,
+    },
+}
+
+/// Reference to a wit type
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    S8,
+    S16,
+    S32,
+    S64,
+    F32,
+    F64,
+    Char,
+    String,
+    List(Box<Type>),
+    Option(Box<Type>),
+    Result {
+        ok: Box<Type>,
+        err: Option<Box<Type>>,
+    },
+    /// The empty tuple is the unit type
+    Tuple(Vec<Type>),
+    /// Type declared in the document or imported with `use`
+    Named(String),
+    /// Borrowed handle to a resource
+    Borrow(String),
+    /// Owned handle to a resource, which the bare name of the resource stands for as well
+    Own(String),
+}
+
+impl Type {
+    pub fn is_unit(&self) -> bool {
+        matches!(self, Type::Tuple(types) if types.is_empty())
+    }
+
+    /// Calls `f` on this type and every type nested in it
+    pub fn walk(&self, f: &mut dyn FnMut(&Type)) {
+        f(self);
+        match self {
+            Type::List(ty) | Type::Option(ty) => ty.walk(f),
+            Type::Result { ok, err } => {
+                ok.walk(f);
+                if let Some(err) = err {
+                    err.walk(f);
+                }
+            }
+            Type::Tuple(types) => types.iter().for_each(|ty| ty.walk(f)),
+            _ => {}
+        }
+    }
+
+    /// Mutable counterpart of [`Type::walk`]
+    pub fn walk_mut(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        f(self);
+        match self {
+            Type::List(ty) | Type::Option(ty) => ty.walk_mut(f),
+            Type::Result { ok, err } => {
+                ok.walk_mut(f);
+                if let Some(err) = err {
+                    err.walk_mut(f);
+                }
+            }
+            Type::Tuple(types) => types.iter_mut().for_each(|ty| ty.walk_mut(f)),
+            _ => {}
+        }
+    }
+
+    /// Name of the declared type this refers to, if any
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Type::Named(name) | Type::Borrow(name) | Type::Own(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Type {
+    type Err = anyhow::Error;
+
+    /// Parse a type written in wit, e.g. `list<option<my-type>>`
+    fn from_str(s: &str) -> Result<Self> {
+        let (ty, rest) = parse_type(s)?;
+        if !rest.trim().is_empty() {
+            bail!("unexpected '{}' after the type in '{s}'", rest.trim());
+        }
+        Ok(ty)
+    }
+}
+
+/// Parse the type at the start of `s`, returning what follows it
+fn parse_type(s: &str) -> Result<(Type, &str)> {
+    let s = s.trim_start();
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '%'))
+        .unwrap_or(s.len());
+    let (name, mut rest) = s.split_at(end);
+    let mut args = vec![];
+    if let Some(after) = rest.trim_start().strip_prefix('<') {
+        rest = after;
+        if let Some(after) = rest.trim_start().strip_prefix('>') {
+            rest = after;
+        } else {
+            loop {
+                let (arg, after) = parse_type(rest)?;
+                args.push(arg);
+                let after = after.trim_start();
+                if let Some(after) = after.strip_prefix(',') {
+                    rest = after;
+                } else if let Some(after) = after.strip_prefix('>') {
+                    rest = after;
+                    break;
+                } else {
+                    bail!("expected ',' or '>' in '{s}'");
+                }
+            }
+        }
+    }
+    let ty = match (name, args.as_slice()) {
+        ("bool", []) => Type::Bool,
+        ("u8", []) => Type::U8,
+        ("u16", []) => Type::U16,
+        ("u32", []) => Type::U32,
+        ("u64", []) => Type::U64,
+        ("s8", []) => Type::S8,
+        ("s16", []) => Type::S16,
+        ("s32", []) => Type::S32,
+        ("s64", []) => Type::S64,
+        ("f32" | "float32", []) => Type::F32,
+        ("f64" | "float64", []) => Type::F64,
+        ("char", []) => Type::Char,
+        ("string", []) => Type::String,
+        // The unit ok type of `result<_, E>`
+        ("_", []) => Type::Tuple(vec![]),
+        ("list", [ty]) => Type::List(Box::new(ty.clone())),
+        ("option", [ty]) => Type::Option(Box::new(ty.clone())),
+        ("result", []) => Type::Result {
+            ok: Box::new(Type::Tuple(vec![])),
+            err: None,
+        },
+        ("result" | "expected", [ok]) => Type::Result {
+            ok: Box::new(ok.clone()),
+            err: None,
+        },
+        ("result" | "expected", [ok, err]) => Type::Result {
+            ok: Box::new(ok.clone()),
+            err: Some(Box::new(err.clone())),
+        },
+        ("tuple", _) => Type::Tuple(args),
+        ("borrow", [Type::Named(name)]) => Type::Borrow(name.clone()),
+        ("own", [Type::Named(name)]) => Type::Own(name.clone()),
+        ("", _) => bail!("expected a type in '{s}'"),
+        (name, []) => Type::Named(name.trim_start_matches('%').to_string()),
+        (name, _) => bail!("unexpected type arguments for '{name}'"),
+    };
+    Ok((ty, rest))
+}
+
+impl Item {
+    /// Name of the type, function or interface declared by this item
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Item::Use(_) => None,
+            Item::Record(Record { name, .. })
+            | Item::Variant(Variant { name, .. })
+            | Item::Enum(Enum { name, .. })
+            | Item::Flags(Flags { name, .. })
+            | Item::Alias(TypeAlias { name, .. })
+            | Item::Function(Function { name, .. })
+            | Item::Resource(Resource { name, .. })
+            | Item::Interface(Interface { name, .. }) => Some(name),
+        }
+    }
+
+    fn name_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Item::Use(_) => None,
+            Item::Record(Record { name, .. })
+            | Item::Variant(Variant { name, .. })
+            | Item::Enum(Enum { name, .. })
+            | Item::Flags(Flags { name, .. })
+            | Item::Alias(TypeAlias { name, .. })
+            | Item::Function(Function { name, .. })
+            | Item::Resource(Resource { name, .. })
+            | Item::Interface(Interface { name, .. }) => Some(name),
+        }
+    }
+
+    pub fn docs(&self) -> &Docs {
+        match self {
+            Item::Use(Use { docs, .. })
+            | Item::Record(Record { docs, .. })
+            | Item::Variant(Variant { docs, .. })
+            | Item::Enum(Enum { docs, .. })
+            | Item::Flags(Flags { docs, .. })
+            | Item::Alias(TypeAlias { docs, .. })
+            | Item::Function(Function { docs, .. })
+            | Item::Resource(Resource { docs, .. })
+            | Item::Interface(Interface { docs, .. }) => docs,
+        }
+    }
+
+    /// Whether this item declares a type which can be referenced by name
+    pub fn is_type(&self) -> bool {
+        matches!(
+            self,
+            Item::Record(_)
+                | Item::Variant(_)
+                | Item::Enum(_)
+                | Item::Flags(_)
+                | Item::Alias(_)
+                | Item::Resource(_)
+        )
+    }
+
+    /// Calls `f` on every type referenced by this item
+    pub fn walk_types(&self, f: &mut dyn FnMut(&Type)) {
+        match self {
+            Item::Use(_) | Item::Enum(_) | Item::Flags(_) => {}
+            Item::Record(record) => record.fields.iter().for_each(|field| field.ty.walk(f)),
+            Item::Variant(variant) => variant
+                .cases
+                .iter()
+                .filter_map(|case| case.ty.as_ref())
+                .for_each(|ty| ty.walk(f)),
+            Item::Alias(alias) => alias.ty.walk(f),
+            Item::Function(func) => func.walk_types(f),
+            Item::Resource(resource) => resource
+                .functions
+                .iter()
+                .for_each(|func| func.walk_types(f)),
+            Item::Interface(interface) => {
+                interface.items.iter().for_each(|item| item.walk_types(f))
+            }
+        }
+    }
+
+    /// Mutable counterpart of [`Item::walk_types`]
+    pub fn walk_types_mut(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        match self {
+            Item::Use(_) | Item::Enum(_) | Item::Flags(_) => {}
+            Item::Record(record) => record
+                .fields
+                .iter_mut()
+                .for_each(|field| field.ty.walk_mut(f)),
+            Item::Variant(variant) => variant
+                .cases
+                .iter_mut()
+                .filter_map(|case| case.ty.as_mut())
+                .for_each(|ty| ty.walk_mut(f)),
+            Item::Alias(alias) => alias.ty.walk_mut(f),
+            Item::Function(func) => func.walk_types_mut(f),
+            Item::Resource(resource) => resource
+                .functions
+                .iter_mut()
+                .for_each(|func| func.walk_types_mut(f)),
+            Item::Interface(interface) => interface
+                .items
+                .iter_mut()
+                .for_each(|item| item.walk_types_mut(f)),
+        }
+    }
+
+    /// A name declared twice within this item, e.g. by `http_config` and `HttpConfig` fields,
+    /// along with what it names: a field, a case, a flag, a parameter or a function of a
+    /// resource
+    ///
+    /// The items of an interface are declarations of their own, they aren't checked.
+    pub fn duplicate_name(&self) -> Option<(&'static str, &str)> {
+        match self {
+            Item::Use(_) | Item::Alias(_) | Item::Interface(_) => None,
+            Item::Record(record) => {
+                first_duplicate(record.fields.iter().map(|f| &f.name)).map(|name| ("field", name))
+            }
+            Item::Variant(variant) => {
+                first_duplicate(variant.cases.iter().map(|c| &c.name)).map(|name| ("case", name))
+            }
+            Item::Enum(enum_) => {
+                first_duplicate(enum_.cases.iter().map(|c| &c.name)).map(|name| ("case", name))
+            }
+            Item::Flags(flags) => {
+                first_duplicate(flags.flags.iter().map(|f| &f.name)).map(|name| ("flag", name))
+            }
+            Item::Function(func) => func.duplicate_param().map(|name| ("parameter", name)),
+            Item::Resource(resource) => first_duplicate(resource.functions.iter().map(|f| &f.name))
+                .map(|name| ("function", name))
+                .or_else(|| {
+                    resource
+                        .functions
+                        .iter()
+                        .find_map(Function::duplicate_param)
+                        .map(|name| ("parameter", name))
+                }),
+        }
+    }
+
+    /// Names of the declared types referenced by this item, in order of appearance
+    pub fn referenced_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        self.walk_types(&mut |ty| {
+            if let Some(name) = ty.name() {
+                if !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+            }
+        });
+        names
+    }
+}
+
+fn collect_resources(items: &[Item], res: &mut Vec<String>) {
+    for item in items {
+        match item {
+            Item::Resource(resource) => res.push(resource.name.clone()),
+            Item::Interface(interface) => collect_resources(&interface.items, res),
+            _ => {}
+        }
+    }
+}
+
+fn resolve_handles(items: &mut [Item], resources: &[String]) {
+    for item in items.iter_mut() {
+        item.walk_types_mut(&mut |ty| {
+            if let Type::Borrow(name) = ty {
+                if !resources.contains(name) {
+                    *ty = Type::Named(name.clone());
+                }
+            }
+        });
+        let functions: Vec<&mut Function> = match item {
+            Item::Function(func) => vec![func],
+            Item::Resource(resource) => resource.functions.iter_mut().collect(),
+            Item::Interface(interface) => {
+                resolve_handles(&mut interface.items, resources);
+                vec![]
+            }
+            _ => vec![],
+        };
+        for param in functions
+            .into_iter()
+            .flat_map(|func| func.params.iter_mut())
+        {
+            if let Type::Named(name) = &param.ty {
+                if resources.contains(name) {
+                    param.ty = Type::Own(name.clone());
+                }
+            }
+        }
+    }
+}
+
+fn rename_declarations(items: &mut [Item], from: &str, to: &str) {
+    for item in items.iter_mut() {
+        if let Item::Interface(interface) = item {
+            rename_declarations(&mut interface.items, from, to);
+        }
+        if let Some(name) = item.name_mut() {
+            if name == from {
+                *name = to.to_string();
+            }
+        }
+    }
+}
+
+impl Interface {
+    /// Names of the types declared in this interface
+    pub fn type_names(&self) -> Vec<&str> {
+        self.items
+            .iter()
+            .filter(|item| item.is_type())
+            .filter_map(Item::name)
+            .collect()
+    }
+}
+
+impl Use {
+    /// Whether `name` is brought into scope by this `use`, under its own name or as a rename
+    pub fn imports(&self, name: &str) -> bool {
+        match &self.names {
+            UseNames::Glob => false,
+            UseNames::Names(names) => names
+                .iter()
+                .any(|import| import.rename.as_ref().unwrap_or(&import.name) == name),
+        }
+    }
+
+    /// Import `name` as well, unless this is a glob
+    pub fn add(&mut self, name: String) {
+        if let UseNames::Names(names) = &mut self.names {
+            names.push(UseName { name, rename: None });
+        }
+    }
+}
+
+fn first_duplicate<'a>(names: impl IntoIterator<Item = &'a String>) -> Option<&'a str> {
+    let mut seen: Vec<&str> = vec![];
+    for name in names {
+        if seen.contains(&name.as_str()) {
+            return Some(name);
+        }
+        seen.push(name);
+    }
+    None
+}
+
+impl Function {
+    /// A parameter name declared twice
+    pub fn duplicate_param(&self) -> Option<&str> {
+        first_duplicate(self.params.iter().map(|param| &param.name))
+    }
+
+    pub fn walk_types(&self, f: &mut dyn FnMut(&Type)) {
+        self.params.iter().for_each(|param| param.ty.walk(f));
+        if let Some(result) = &self.result {
+            result.walk(f);
+        }
+    }
+
+    pub fn walk_types_mut(&mut self, f: &mut dyn FnMut(&mut Type)) {
+        self.params
+            .iter_mut()
+            .for_each(|param| param.ty.walk_mut(f));
+        if let Some(result) = &mut self.result {
+            result.walk_mut(f);
+        }
+    }
+}
+
+impl Document {
+    pub fn new(items: Vec<Item>) -> Self {
+        Self {
+            package: None,
+            name: None,
+            docs: vec![],
+            items,
+            worlds: vec![],
+        }
+    }
+
+    /// Append the items of `other` to this document
+    pub fn merge(&mut self, other: Document) {
+        self.items.extend(other.items);
+        for world in other.worlds {
+            for item in world.imports {
+                self.add_to_world(world.name.clone(), false, item);
+            }
+            for item in world.exports {
+                self.add_to_world(world.name.clone(), true, item);
+            }
+        }
+    }
+
+    /// Keep only the items for which `f` returns `true`
+    pub fn retain(&mut self, f: impl FnMut(&Item) -> bool) {
+        self.items.retain(f);
+    }
+
+    /// Find the item declaring `name`
+    pub fn get(&self, name: &str) -> Option<&Item> {
+        self.items.iter().find(|item| item.name() == Some(name))
+    }
+
+    /// Rename the items declaring `from`, in interfaces as well, along with every reference to
+    /// them
+    pub fn rename(&mut self, from: &str, to: &str) {
+        rename_declarations(&mut self.items, from, to);
+        for item in self.items.iter_mut() {
+            item.walk_types_mut(&mut |ty| {
+                if let Type::Named(name) | Type::Borrow(name) | Type::Own(name) = ty {
+                    if name == from {
+                        *name = to.to_string();
+                    }
+                }
+            });
+        }
+    }
+
+    /// Items brought into scope by importing `imports` from this document, along with the
+    /// types they refer to
+    ///
+    /// Items imported under another name are declared with it, or aliased when they are
+    /// imported under their own name as well.
+    pub fn import(&self, imports: &[UseNames]) -> Document {
+        let glob = imports.iter().any(|names| matches!(names, UseNames::Glob));
+        let names: Vec<&UseName> = imports
+            .iter()
+            .flat_map(|names| match names {
+                UseNames::Glob => &[][..],
+                UseNames::Names(names) => names.as_slice(),
+            })
+            .collect();
+        let mut keep: Vec<String> = if glob {
+            self.items
+                .iter()
+                .filter_map(Item::name)
+                .map(ToString::to_string)
+                .collect()
+        } else {
+            names.iter().map(|name| name.name.clone()).collect()
+        };
+        let mut next = 0;
+        while next < keep.len() {
+            if let Some(item) = self.get(&keep[next]) {
+                for name in item.referenced_names() {
+                    if !keep.contains(&name) {
+                        keep.push(name);
+                    }
+                }
+            }
+            next += 1;
+        }
+
+        let mut doc = self.clone();
+        doc.retain(|item| {
+            item.name()
+                .map_or(false, |name| keep.iter().any(|k| k == name))
+        });
+        for name in &names {
+            let rename = match &name.rename {
+                Some(rename) => rename,
+                None => continue,
+            };
+            let imported_as_is = glob
+                || names
+                    .iter()
+                    .any(|n| n.name == name.name && n.rename.is_none());
+            if !imported_as_is {
+                doc.rename(&name.name, rename);
+            } else if self.get(&name.name).map_or(false, Item::is_type) {
+                doc.items.push(Item::Alias(TypeAlias {
+                    name: rename.clone(),
+                    docs: vec![],
+                    ty: Type::Named(name.name.clone()),
+                }));
+            }
+        }
+        doc
+    }
+
+    /// Replace the references to the type named `name` by `ty`
+    pub fn replace_type(&mut self, name: &str, ty: &Type) {
+        for item in self.items.iter_mut() {
+            item.walk_types_mut(&mut |reference| {
+                if reference.name() == Some(name) {
+                    *reference = ty.clone();
+                }
+            });
+        }
+    }
+
+    /// Names of the types declared at the top level of the document
+    pub fn type_names(&self) -> Vec<&str> {
+        self.items
+            .iter()
+            .filter(|item| item.is_type())
+            .filter_map(Item::name)
+            .collect()
+    }
+
+    /// Import `item` into the world `name`, or export it, declaring the world if needed
+    pub fn add_to_world(&mut self, name: Option<String>, export: bool, item: WorldItem) {
+        let index = match self.worlds.iter().position(|world| world.name == name) {
+            Some(index) => index,
+            None => {
+                self.worlds.push(World {
+                    name,
+                    imports: vec![],
+                    exports: vec![],
+                });
+                self.worlds.len() - 1
+            }
+        };
+        let world = &mut self.worlds[index];
+        let items = if export {
+            &mut world.exports
+        } else {
+            &mut world.imports
+        };
+        if !items.contains(&item) {
+            items.push(item);
+        }
+    }
+
+    /// Whether `item` is a function of a world, declared in the interface named `interface`
+    /// (`None` for the interface of the document)
+    pub fn is_world_function(&self, interface: Option<&str>, item: &Item) -> bool {
+        let func = match item {
+            Item::Function(func) => func,
+            _ => return false,
+        };
+        self.worlds
+            .iter()
+            .flat_map(|world| world.imports.iter().chain(&world.exports))
+            .any(|world_item| match world_item {
+                WorldItem::Function {
+                    interface: declared_in,
+                    name,
+                } => declared_in.as_deref() == interface && *name == func.name,
+                WorldItem::Interface(_) => false,
+            })
+    }
+
+    /// Pass resources declared in the document by handle: parameters taking a reference become
+    /// `borrow<...>` handles and the ones taking a resource by value `own<...>` handles
+    ///
+    /// References to other types are plain references to them.
+    pub fn resolve_handles(&mut self) {
+        let mut resources = vec![];
+        collect_resources(&self.items, &mut resources);
+        resolve_handles(&mut self.items, &resources);
+    }
+
+    /// Move the functions of worlds declared in interfaces to the document itself
+    ///
+    /// Worlds declare their functions inline, so this only changes where the types they
+    /// reference are `use`d from, letting the worlds be printed in a single document.
+    pub fn hoist_world_functions(&mut self) {
+        let mut hoisted = vec![];
+        for world in self.worlds.iter_mut() {
+            for item in world.imports.iter_mut().chain(world.exports.iter_mut()) {
+                if let WorldItem::Function { interface, name } = item {
+                    if let Some(interface) = interface.take() {
+                        hoisted.push((interface, name.clone()));
+                    }
+                }
+            }
+        }
+        let mut functions = vec![];
+        for item in self.items.iter_mut() {
+            if let Item::Interface(interface) = item {
+                let (moved, kept) = interface.items.drain(..).partition(|item| match item {
+                    Item::Function(func) => hoisted
+                        .iter()
+                        .any(|(from, name)| *from == interface.name && *name == func.name),
+                    _ => false,
+                });
+                interface.items = kept;
+                functions.extend::<Vec<Item>>(moved);
+            }
+        }
+        self.items.extend(functions);
+    }
+
+    /// Names of the types declared in the document, in its interfaces included
+    pub fn all_type_names(&self) -> Vec<&str> {
+        let mut names = self.type_names();
+        for item in &self.items {
+            if let Item::Interface(interface) = item {
+                names.extend(interface.type_names());
+            }
+        }
+        names
+    }
+
+    /// Split the items into one document per file, as named by `file_of`, in order of first
+    /// appearance
+    ///
+    /// Each document is named after its file and `use`s the types it refers to from the other
+    /// ones, while the `use`s of this document are repeated in all of them. An interface put in
+    /// the file of the same name becomes the interface of that file: its items and docs are the
+    /// document's.
+    pub fn split(&self, mut file_of: impl FnMut(&Item) -> String) -> Vec<Document> {
+        let mut docs: Vec<Document> = vec![];
+        for item in self.items.iter() {
+            if let Item::Use(_) = item {
+                continue;
+            }
+            let file = file_of(item);
+            let index = match docs.iter().position(|doc| doc.name.as_ref() == Some(&file)) {
+                Some(index) => index,
+                None => {
+                    docs.push(Document {
+                        package: self.package.clone(),
+                        name: Some(file.clone()),
+                        docs: vec![],
+                        items: vec![],
+                        worlds: vec![],
+                    });
+                    docs.len() - 1
+                }
+            };
+            let doc = &mut docs[index];
+            match item {
+                Item::Interface(interface) if interface.name == file => {
+                    doc.docs = interface.docs.clone();
+                    doc.items.extend(interface.items.iter().cloned());
+                }
+                item => doc.items.push(item.clone()),
+            }
+        }
+
+        let declared: Vec<(String, String)> = docs
+            .iter()
+            .flat_map(|doc| {
+                let file = doc.name.clone().unwrap_or_default();
+                doc.type_names()
+                    .into_iter()
+                    .map(move |name| (name.to_string(), file.clone()))
+            })
+            .collect();
+        for doc in docs.iter_mut() {
+            let mut uses: Vec<Use> = vec![];
+            let referenced: Vec<String> =
+                doc.items.iter().flat_map(Item::referenced_names).collect();
+            for name in referenced {
+                if doc.get(&name).is_some() {
+                    continue;
+                }
+                let from = match declared.iter().find(|(declared, _)| *declared == name) {
+                    Some((_, from)) => from,
+                    None => continue,
+                };
+                let name = UseName { name, rename: None };
+                match uses.iter_mut().find(|import| &import.from == from) {
+                    Some(Use {
+                        names: UseNames::Names(names),
+                        ..
+                    }) => {
+                        if !names.contains(&name) {
+                            names.push(name);
+                        }
+                    }
+                    Some(_) => {}
+                    None => uses.push(Use {
+                        docs: vec![],
+                        from: from.clone(),
+                        names: UseNames::Names(vec![name]),
+                    }),
+                }
+            }
+            let imports = self
+                .items
+                .iter()
+                .filter(|item| matches!(item, Item::Use(_)))
+                .cloned()
+                .chain(uses.into_iter().map(Item::Use));
+            doc.items.splice(0..0, imports);
+        }
+        docs
+    }
+
+    /// Reorder the items, recursively in interfaces, see [`Order`]
+    pub fn sort(&mut self, order: Order) {
+        if order == Order::Topological {
+            sort_topologically(&mut self.items);
+        }
+    }
+}
+
+/// Order of the declarations in a generated document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// The order the items are declared in the Rust sources, followed by the items imported
+    /// from dependencies
+    Source,
+    /// `use`s first, then each type after the types it refers to, then functions and
+    /// interfaces, source order breaking ties
+    Topological,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Source
+    }
+}
+
+impl FromStr for Order {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "source" => Ok(Order::Source),
+            "topological" => Ok(Order::Topological),
+            other => bail!("unknown order '{other}', expected 'source' or 'topological'"),
+        }
+    }
+}
+
+impl std::fmt::Display for Order {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Order::Source => write!(f, "source"),
+            Order::Topological => write!(f, "topological"),
+        }
+    }
+}
+
+fn sort_topologically(items: &mut Vec<Item>) {
+    for item in items.iter_mut() {
+        if let Item::Interface(interface) = item {
+            sort_topologically(&mut interface.items);
+        }
+    }
+    let mut remaining: Vec<Option<Item>> = std::mem::take(items).into_iter().map(Some).collect();
+    // Uses, then types, then the rest
+    for pass in 0..3 {
+        for i in 0..remaining.len() {
+            let rank = match &remaining[i] {
+                Some(Item::Use(_)) => 0,
+                Some(item) if item.is_type() => 1,
+                Some(_) => 2,
+                None => continue,
+            };
+            if rank == pass {
+                push_after_dependencies(&mut remaining, i, items);
+            }
+        }
+    }
+}
+
+/// Move `remaining[i]` to `sorted`, after the types it refers to which aren't sorted yet
+///
+/// An item is taken out of `remaining` before its dependencies are visited, so recursive
+/// types don't loop.
+fn push_after_dependencies(remaining: &mut [Option<Item>], i: usize, sorted: &mut Vec<Item>) {
+    let item = match remaining[i].take() {
+        Some(item) => item,
+        None => return,
+    };
+    for name in item.referenced_names() {
+        let dependency = remaining.iter().position(
+            |other| matches!(other, Some(other) if other.is_type() && other.name() == Some(&name)),
+        );
+        if let Some(dependency) = dependency {
+            push_after_dependencies(remaining, dependency, sorted);
+        }
+    }
+    sorted.push(item);
+}
+
+/// Conversion from the definitions parsed by `wit_parser`
+impl Document {
+    /// Items of a parsed wit interface, in the order the parser declared them
+    ///
+    /// Types and resources of other interfaces are imported with `use`. A `static new`
+    /// function returning its resource is the resource's constructor, and the methods
+    /// documented with `///@mutable` take `&mut self`.
+    pub fn from_interface(interface: &wit_parser::Interface) -> Result<Document> {
+        let mut uses: Vec<Use> = vec![];
+        let mut items = vec![];
+        for (_, def) in interface.types.iter() {
+            let name = match &def.name {
+                Some(name) => name.clone(),
+                None => continue,
+            };
+            if let Some(module) = &def.foreign_module {
+                add_use(&mut uses, module, name);
+                continue;
+            }
+            let docs = parsed_docs(&def.docs);
+            let item = match &def.kind {
+                TypeDefKind::Record(record) => Item::Record(Record {
+                    name,
+                    docs,
+                    fields: record
+                        .fields
+                        .iter()
+                        .map(|field| {
+                            Ok(Field {
+                                name: field.name.clone(),
+                                docs: parsed_docs(&field.docs),
+                                ty: parsed_type(interface, &field.ty)?,
+                            })
+                        })
+                        .collect::<Result<Vec<Field>>>()?,
+                }),
+                TypeDefKind::Flags(flags) => Item::Flags(Flags {
+                    name,
+                    docs,
+                    flags: flags
+                        .flags
+                        .iter()
+                        .map(|flag| Flag {
+                            name: flag.name.clone(),
+                            docs: parsed_docs(&flag.docs),
+                        })
+                        .collect(),
+                }),
+                TypeDefKind::Variant(variant) => Item::Variant(Variant {
+                    name,
+                    docs,
+                    cases: variant
+                        .cases
+                        .iter()
+                        .map(|case| {
+                            Ok(Case {
+                                name: case.name.clone(),
+                                docs: parsed_docs(&case.docs),
+                                ty: match case.ty {
+                                    ParsedType::Unit => None,
+                                    ty => Some(parsed_type(interface, &ty)?),
+                                },
+                            })
+                        })
+                        .collect::<Result<Vec<Case>>>()?,
+                }),
+                TypeDefKind::Enum(enm) => Item::Enum(Enum {
+                    name,
+                    docs,
+                    cases: enm
+                        .cases
+                        .iter()
+                        .map(|case| EnumCase {
+                            name: case.name.clone(),
+                            docs: parsed_docs(&case.docs),
+                        })
+                        .collect(),
+                }),
+                kind => Item::Alias(TypeAlias {
+                    ty: anonymous_type(interface, kind)
+                        .with_context(|| format!("can't import `{name}`"))?,
+                    name,
+                    docs,
+                }),
+            };
+            items.push(item);
+        }
+
+        for (id, resource) in interface.resources.iter() {
+            if let Some(module) = &resource.foreign_module {
+                add_use(&mut uses, module, resource.name.clone());
+                continue;
+            }
+            let functions = interface
+                .functions
+                .iter()
+                .filter_map(|func| match &func.kind {
+                    ParsedFunctionKind::Static { resource, name }
+                    | ParsedFunctionKind::Method { resource, name }
+                        if *resource == id =>
+                    {
+                        Some(parsed_function(interface, func, name))
+                    }
+                    _ => None,
+                })
+                .collect::<Result<Vec<Function>>>()?;
+            items.push(Item::Resource(Resource {
+                name: resource.name.clone(),
+                docs: parsed_docs(&resource.docs),
+                functions,
+            }));
+        }
+
+        for func in &interface.functions {
+            if let ParsedFunctionKind::Freestanding = func.kind {
+                items.push(Item::Function(parsed_function(
+                    interface, func, &func.name,
+                )?));
+            }
+        }
+
+        for (_, nested) in interface.interfaces.iter() {
+            let doc = Document::from_interface(nested)?;
+            items.push(Item::Interface(Interface {
+                name: nested.name.clone(),
+                docs: vec![],
+                items: doc.items,
+            }));
+        }
+
+        Ok(Document::new(
+            uses.into_iter().map(Item::Use).chain(items).collect(),
+        ))
+    }
+}
+
+fn add_use(uses: &mut Vec<Use>, module: &str, name: String) {
+    let name = UseName { name, rename: None };
+    match uses.iter_mut().find(|import| import.from == module) {
+        Some(Use {
+            names: UseNames::Names(names),
+            ..
+        }) => names.push(name),
+        _ => uses.push(Use {
+            docs: vec![],
+            from: module.to_string(),
+            names: UseNames::Names(vec![name]),
+        }),
+    }
+}
+
+fn parsed_docs(docs: &wit_parser::Docs) -> Docs {
+    docs.contents.as_deref().map_or_else(Vec::new, |contents| {
+        contents.lines().map(ToString::to_string).collect()
+    })
+}
+
+fn parsed_function(
+    interface: &wit_parser::Interface,
+    func: &wit_parser::Function,
+    name: &str,
+) -> Result<Function> {
+    let mut docs = parsed_docs(&func.docs);
+    let mutable = docs.iter().any(|line| line == "@mutable");
+    docs.retain(|line| line != "@mutable");
+    let result = match func.result {
+        ParsedType::Unit => None,
+        ty => Some(parsed_type(interface, &ty)?),
+    };
+    let kind = match &func.kind {
+        // Functions of an interface generated from a trait can take `&mut self`
+        ParsedFunctionKind::Freestanding if mutable => FunctionKind::Method { mutable },
+        ParsedFunctionKind::Freestanding => FunctionKind::Freestanding,
+        ParsedFunctionKind::Method { .. } => FunctionKind::Method { mutable },
+        ParsedFunctionKind::Static { resource, .. }
+            if name == "new" && func.result == ParsedType::Handle(*resource) =>
+        {
+            FunctionKind::Constructor
+        }
+        // Consumers take the owned handle as their first parameter
+        ParsedFunctionKind::Static { resource, .. }
+            if func.params.first().map_or(false, |(name, ty)| {
+                name == "self" && *ty == ParsedType::Handle(*resource)
+            }) =>
+        {
+            FunctionKind::Consumer
+        }
+        ParsedFunctionKind::Static { .. } => FunctionKind::Static,
+    };
+    let mut params = func
+        .params
+        .iter()
+        .map(|(name, ty)| {
+            Ok(Param {
+                name: name.clone(),
+                ty: parsed_type(interface, ty)?,
+            })
+        })
+        .collect::<Result<Vec<Param>>>()?;
+    if let (FunctionKind::Consumer, Some(Param { ty, .. })) = (kind, params.first_mut()) {
+        if let Type::Named(resource) = ty {
+            *ty = Type::Own(resource.clone());
+        }
+    }
+    Ok(Function {
+        name: name.to_string(),
+        docs,
+        kind,
+        params,
+        result,
+    })
+}
+
+fn parsed_type(interface: &wit_parser::Interface, ty: &ParsedType) -> Result<Type> {
+    let res = match ty {
+        ParsedType::Unit => Type::Tuple(vec![]),
+        ParsedType::Bool => Type::Bool,
+        ParsedType::U8 => Type::U8,
+        ParsedType::U16 => Type::U16,
+        ParsedType::U32 => Type::U32,
+        ParsedType::U64 => Type::U64,
+        ParsedType::S8 => Type::S8,
+        ParsedType::S16 => Type::S16,
+        ParsedType::S32 => Type::S32,
+        ParsedType::S64 => Type::S64,
+        ParsedType::Float32 => Type::F32,
+        ParsedType::Float64 => Type::F64,
+        ParsedType::Char => Type::Char,
+        ParsedType::String => Type::String,
+        ParsedType::Handle(id) => Type::Named(interface.resources[*id].name.clone()),
+        ParsedType::Id(id) => {
+            let def = &interface.types[*id];
+            match &def.name {
+                Some(name) => Type::Named(name.clone()),
+                None => anonymous_type(interface, &def.kind)?,
+            }
+        }
+    };
+    Ok(res)
+}
+
+/// Type written in place, e.g. `list<u8>`
+fn anonymous_type(interface: &wit_parser::Interface, kind: &TypeDefKind) -> Result<Type> {
+    let res = match kind {
+        TypeDefKind::Type(ty) => parsed_type(interface, ty)?,
+        TypeDefKind::List(ty) => Type::List(Box::new(parsed_type(interface, ty)?)),
+        TypeDefKind::Option(ty) => Type::Option(Box::new(parsed_type(interface, ty)?)),
+        TypeDefKind::Expected(expected) => Type::Result {
+            ok: Box::new(parsed_type(interface, &expected.ok)?),
+            err: match expected.err {
+                ParsedType::Unit => None,
+                err => Some(Box::new(parsed_type(interface, &err)?)),
+            },
+        },
+        TypeDefKind::Tuple(tuple) => Type::Tuple(
+            tuple
+                .types
+                .iter()
+                .map(|ty| parsed_type(interface, ty))
+                .collect::<Result<Vec<Type>>>()?,
+        ),
+        TypeDefKind::Record(_)
+        | TypeDefKind::Flags(_)
+        | TypeDefKind::Variant(_)
+        | TypeDefKind::Enum(_) => bail!("records, flags, variants and enums must be named"),
+        TypeDefKind::Union(_) | TypeDefKind::Future(_) | TypeDefKind::Stream(_) => {
+            bail!("unions, futures and streams aren't supported by witgen")
+        }
+    };
+    Ok(res)
+}
@@ -9,18 +9,35 @@ use std::{
     fs,
     path::{Path, PathBuf},
 };
-use syn::File;
+use syn::{File, Item, Lit, Meta, MetaNameValue, NestedMeta};
 pub use syn_file_expand::read_full_crate_source_code;
+use visitor::CfgVisitor;
 pub use wit_parser::Interface;
 
+mod attrs;
+pub use attrs::{is_witgen_attr, WitgenAttrs};
+mod cfg;
+pub use cfg::Cfg;
+mod deep;
+pub use deep::include_referenced_types;
+mod dialect;
+pub use dialect::{Dialect, PackageName};
+mod diagnostics;
+pub use diagnostics::{Diagnostic, Diagnostics};
 pub mod generator;
+mod generics;
+pub mod ir;
+pub mod printer;
+mod references;
+pub use references::check_references;
+pub mod rust;
 mod wit;
 pub use wit::Wit;
 mod util;
 pub mod visitor;
 
 /// Convence function for
-/// ```
+/// ```rust,ignore
 /// let wit: Result<Wit> = tokens.try_into()
 /// ```
 pub fn parse_tokens(tokens: proc_macro2::TokenStream) -> Result<Wit> {
@@ -57,16 +74,100 @@ pub fn resolve_wit_files(root_paths: &[PathBuf], name: &str) -> Result<(PathBuf,
 }
 
 /// Read a crate starting from a single file then parse into a file
+///
+/// `cfg` predicates are evaluated for the host without any feature, see
+/// [`parse_crate_as_file_with_cfg`].
 pub fn parse_crate_as_file(path: &Path) -> Result<File> {
-    if let Ok(file) = read_full_crate_source_code(path, |_| Ok(false)) {
-        Ok(file)
-    } else {
-        bail!("Failed to parse crate source {:?}", path)
+    parse_crate_as_file_with_cfg(path, &Cfg::host())
+}
+
+/// Read a crate starting from a single file then parse into a file, leaving out the modules,
+/// items, fields, variants and methods disabled by `#[cfg(...)]` under `cfg`
+pub fn parse_crate_as_file_with_cfg(path: &Path, cfg: &Cfg) -> Result<File> {
+    let eval = |meta| {
+        cfg.eval(&NestedMeta::Meta(meta))
+            .map_err(|err| err.to_string().into())
+    };
+    let mut file = match read_full_crate_source_code(path, eval) {
+        Ok(file) => file,
+        Err(_) => bail!("Failed to parse crate source {:?}", path),
+    };
+    CfgVisitor::strip(&mut file, cfg)?;
+    Ok(file)
+}
+
+/// Files making up the crate whose root is `path`, following `mod` declarations
+///
+/// Modules whose file doesn't exist are listed with the paths they can be declared in, and
+/// files which don't parse are listed without their modules.
+pub fn crate_source_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    collect_source_files(path, dir, &mut files);
+    files
+}
+
+/// Add `path` and the files of the modules it declares, the latter being relative to `mod_dir`
+fn collect_source_files(path: &Path, mod_dir: &Path, files: &mut Vec<PathBuf>) {
+    files.push(path.to_path_buf());
+    if let Ok(file) = fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|source| Ok(syn::parse_file(&source)?))
+    {
+        collect_mod_files(
+            &file.items,
+            path.parent().unwrap_or(mod_dir),
+            mod_dir,
+            files,
+        );
+    }
+}
+
+fn collect_mod_files(items: &[Item], dir: &Path, mod_dir: &Path, files: &mut Vec<PathBuf>) {
+    for item in items {
+        let item_mod = match item {
+            Item::Mod(item_mod) => item_mod,
+            _ => continue,
+        };
+        let name = item_mod.ident.to_string();
+        let path_attr = item_mod
+            .attrs
+            .iter()
+            .find_map(|attr| match attr.parse_meta() {
+                Ok(Meta::NameValue(MetaNameValue {
+                    path,
+                    lit: Lit::Str(lit),
+                    ..
+                })) if path.is_ident("path") => Some(lit.value()),
+                _ => None,
+            });
+        match (&item_mod.content, path_attr) {
+            (Some((_, items)), _) => {
+                let mod_dir = mod_dir.join(&name);
+                collect_mod_files(items, &mod_dir, &mod_dir, files);
+            }
+            (None, Some(path)) => {
+                let path = dir.join(path);
+                let mod_dir = path.parent().unwrap_or(dir).to_path_buf();
+                collect_source_files(&path, &mod_dir, files);
+            }
+            (None, None) => {
+                let file = mod_dir.join(format!("{name}.rs"));
+                let mod_rs = mod_dir.join(&name).join("mod.rs");
+                if file.exists() {
+                    collect_source_files(&file, &mod_dir.join(&name), files);
+                } else if mod_rs.exists() {
+                    collect_source_files(&mod_rs, &mod_dir.join(&name), files);
+                } else {
+                    files.extend([file, mod_rs]);
+                }
+            }
+        }
     }
 }
 
 /// Convence function for
-/// ```
+/// ```rust,ignore
 /// let wit: Wit = file.into();
 /// ```
 pub fn parse_file(file: File) -> Wit {
@@ -0,0 +1,523 @@
+use anyhow::{bail, Context, Result};
+use std::{borrow::Cow, fmt::Write};
+
+use crate::{
+    ir::{
+        Docs, Document, Enum, Flags, Function, FunctionKind, Interface, Item, Record, Resource,
+        Type, TypeAlias, Use, UseNames, Variant, World, WorldItem,
+    },
+    Dialect,
+};
+
+/// Renders the wit intermediate representation in a given dialect
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Printer {
+    pub dialect: Dialect,
+}
+
+impl Printer {
+    pub fn new(dialect: Dialect) -> Self {
+        Self { dialect }
+    }
+
+    /// Print a whole document
+    ///
+    /// In the component dialect every item is placed in an interface named after the
+    /// document, or the package, while traits and modules annotated with `#[witgen]` become
+    /// interfaces of their own which `use` the types they reference from the package interface
+    /// or from the other interfaces. `use`s of specific names are placed in
+    /// the package interface, glob ones can't be expressed and are left out.
+    /// ```ts
+    /// package my-ns:my-crate@0.1.0;
+    ///
+    /// interface my-crate {
+    ///   record foo {
+    ///     a: string
+    ///   }
+    ///
+    ///   bar: func(foo: foo) -> result<string>;
+    /// }
+    /// ```
+    pub fn print_document(&self, doc: &Document) -> Result<String> {
+        match self.dialect {
+            Dialect::Legacy => self.print_items(&doc.items, 0),
+            Dialect::Component => {
+                let package = doc
+                    .package
+                    .as_ref()
+                    .context("a component model document needs a package name")?;
+                let imports: Vec<&Use> = doc
+                    .items
+                    .iter()
+                    .filter_map(|item| match item {
+                        Item::Use(import) if import.names != UseNames::Glob => Some(import),
+                        _ => None,
+                    })
+                    .collect();
+                let (interfaces, items): (Vec<&Item>, Vec<&Item>) = doc
+                    .items
+                    .iter()
+                    .filter(|item| !matches!(item, Item::Use(_)))
+                    .filter(|item| !doc.is_world_function(None, item))
+                    .partition(|item| matches!(item, Item::Interface(_)));
+                let main_name = doc.name.as_deref().unwrap_or(&package.name);
+                let main = self.ident(main_name);
+                let scope = Scope {
+                    main: main_name,
+                    type_names: doc.type_names(),
+                    imports: &imports,
+                    interfaces: interfaces
+                        .iter()
+                        .filter_map(|item| match item {
+                            Item::Interface(interface) => Some(interface),
+                            _ => None,
+                        })
+                        .collect(),
+                };
+
+                let mut res = format!("package {package};\n\n");
+                let mut body = String::new();
+                for import in &imports {
+                    body.push_str(&self.print_use(import, 1)?);
+                }
+                if !imports.is_empty() && !items.is_empty() {
+                    body.push('\n');
+                }
+                body.push_str(&self.print_items(items, 1)?);
+                writeln!(
+                    &mut res,
+                    "{}interface {main} {{\n{body}}}",
+                    print_docs(&doc.docs, 0)
+                )?;
+                for item in &interfaces {
+                    let mut interface = match item {
+                        Item::Interface(interface) => interface.clone(),
+                        _ => unreachable!(),
+                    };
+                    interface
+                        .items
+                        .retain(|item| !doc.is_world_function(Some(&interface.name), item));
+                    let uses = scope.uses(
+                        &format!("interface `{}`", interface.name),
+                        Some(&interface),
+                        &interface.items,
+                    )?;
+                    for (i, import) in uses.into_iter().enumerate() {
+                        interface.items.insert(i, Item::Use(import));
+                    }
+                    write!(
+                        &mut res,
+                        "\n{}",
+                        self.print_item(&Item::Interface(interface), 0)?
+                    )?;
+                }
+                for world in &doc.worlds {
+                    write!(&mut res, "\n{}", self.print_world(doc, world, &scope)?)?;
+                }
+                Ok(res)
+            }
+        }
+    }
+
+    /// Print a world of the component dialect, importing and exporting interfaces and functions
+    /// of the document
+    ///
+    /// Functions are declared inline, and the world `use`s the types they reference.
+    /// ```ts
+    /// world my-crate-world {
+    ///   use my-crate.{foo};
+    ///
+    ///   import my-interface;
+    ///   export bar: func(foo: foo) -> result<string>;
+    /// }
+    /// ```
+    fn print_world(&self, doc: &Document, world: &World, scope: &Scope) -> Result<String> {
+        let world_name = match (&world.name, &doc.package) {
+            (Some(name), _) => self.ident(name).into_owned(),
+            (None, Some(package)) => self.ident(&format!("{}-world", package.name)).into_owned(),
+            (None, None) => bail!("a component model document needs a package name"),
+        };
+        let mut functions: Vec<Item> = vec![];
+        let mut body = String::new();
+        for (keyword, items) in [("import", &world.imports), ("export", &world.exports)] {
+            for item in items {
+                match item {
+                    WorldItem::Interface(interface) => {
+                        let interface = interface.as_deref().unwrap_or(scope.main);
+                        writeln!(&mut body, "  {keyword} {};", self.ident(interface))?;
+                    }
+                    WorldItem::Function { interface, name } => {
+                        let declared = match interface {
+                            None => doc.items.iter().collect::<Vec<_>>(),
+                            Some(interface) => scope
+                                .interfaces
+                                .iter()
+                                .filter(|other| other.name == *interface)
+                                .flat_map(|other| &other.items)
+                                .collect(),
+                        };
+                        let func = declared
+                            .into_iter()
+                            .find_map(|item| match item {
+                                Item::Function(func) if func.name == *name => Some(func),
+                                _ => None,
+                            })
+                            .with_context(|| {
+                                format!(
+                                    "function `{name}` of world `{world_name}` isn't declared in this document"
+                                )
+                            })?;
+                        body.push_str(&print_docs(&func.docs, 1));
+                        writeln!(&mut body, "  {keyword} {}", self.print_function(func)?)?;
+                        functions.push(Item::Function(func.clone()));
+                    }
+                }
+            }
+        }
+        let mut res = format!("world {world_name} {{\n");
+        let uses = scope.uses(&format!("world `{world_name}`"), None, &functions)?;
+        for import in &uses {
+            res.push_str(&self.print_use(import, 1)?);
+        }
+        if !uses.is_empty() {
+            res.push('\n');
+        }
+        res.push_str(&body);
+        res.push_str("}\n");
+        Ok(res)
+    }
+
+    /// Print items separated by blank lines
+    pub fn print_items<'a>(
+        &self,
+        items: impl IntoIterator<Item = &'a Item>,
+        depth: usize,
+    ) -> Result<String> {
+        Ok(items
+            .into_iter()
+            .map(|item| self.print_item(item, depth))
+            .collect::<Result<Vec<String>>>()?
+            .join("\n"))
+    }
+
+    pub fn print_item(&self, item: &Item, depth: usize) -> Result<String> {
+        let pad = indent(depth);
+        let docs = print_docs(item.docs(), depth);
+        let term = self.terminator();
+        let res = match item {
+            Item::Use(import) => self.print_use(import, depth)?,
+            Item::Record(Record { name, fields, .. }) => {
+                let fields = fields
+                    .iter()
+                    .map(|field| {
+                        Ok(format!(
+                            "{}{pad}  {}: {}",
+                            print_docs(&field.docs, depth + 1),
+                            self.ident(&field.name),
+                            self.print_type(&field.ty)?
+                        ))
+                    })
+                    .collect::<Result<Vec<String>>>()?
+                    .join(",\n");
+                let name = self.ident(name);
+                format!("{pad}record {name} {{\n{fields}\n{pad}}}\n")
+            }
+            Item::Variant(Variant { name, cases, .. }) => {
+                let cases = cases
+                    .iter()
+                    .map(|case| {
+                        let ty = match &case.ty {
+                            Some(ty) => format!("({})", self.print_type(ty)?),
+                            None => String::new(),
+                        };
+                        Ok(format!(
+                            "{}{pad}  {}{ty},",
+                            print_docs(&case.docs, depth + 1),
+                            self.ident(&case.name)
+                        ))
+                    })
+                    .collect::<Result<Vec<String>>>()?
+                    .join("\n");
+                let name = self.ident(name);
+                format!("{pad}variant {name} {{\n{cases}\n{pad}}}\n")
+            }
+            Item::Enum(Enum { name, cases, .. }) => {
+                let cases = cases
+                    .iter()
+                    .map(|case| {
+                        let docs = print_docs(&case.docs, depth + 1);
+                        format!("{docs}{pad}  {},", self.ident(&case.name))
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                let name = self.ident(name);
+                format!("{pad}enum {name} {{\n{cases}\n{pad}}}\n")
+            }
+            Item::Flags(Flags { name, flags, .. }) => {
+                let flags = flags
+                    .iter()
+                    .map(|flag| {
+                        let docs = print_docs(&flag.docs, depth + 1);
+                        format!("{docs}{pad}  {},", self.ident(&flag.name))
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                let name = self.ident(name);
+                format!("{pad}flags {name} {{\n{flags}\n{pad}}}\n")
+            }
+            Item::Alias(TypeAlias { name, ty, .. }) => {
+                let name = self.ident(name);
+                format!("{pad}type {name} = {}{term}\n", self.print_type(ty)?)
+            }
+            Item::Function(func) => format!("{pad}{}\n", self.print_function(func)?),
+            Item::Resource(Resource {
+                name, functions, ..
+            }) => {
+                let mut res = format!("{pad}resource {} {{\n", self.ident(name));
+                for func in functions {
+                    res.push_str(&self.print_method(func, depth + 1)?);
+                }
+                writeln!(&mut res, "{pad}}}")?;
+                res
+            }
+            Item::Interface(Interface { name, items, .. }) => {
+                let mut res = format!("{pad}interface {} {{\n", self.ident(name));
+                for item in items {
+                    match item {
+                        // Functions of an interface aren't separated by blank lines
+                        Item::Function(func) => res.push_str(&self.print_method(func, depth + 1)?),
+                        other => writeln!(&mut res, "{}", self.print_item(other, depth + 1)?)?,
+                    }
+                }
+                writeln!(&mut res, "{pad}}}")?;
+                res
+            }
+        };
+        Ok(format!("{docs}{res}"))
+    }
+
+    fn print_use(&self, import: &Use, depth: usize) -> Result<String> {
+        let pad = indent(depth);
+        let names = match &import.names {
+            UseNames::Glob => "*".to_string(),
+            UseNames::Names(names) => names
+                .iter()
+                .map(|name| match &name.rename {
+                    Some(rename) => format!("{} as {}", self.ident(&name.name), self.ident(rename)),
+                    None => self.ident(&name.name).into_owned(),
+                })
+                .collect::<Vec<String>>()
+                .join(", "),
+        };
+        let from = self.ident(&import.from);
+        let res = match (self.dialect, &import.names) {
+            (Dialect::Legacy, UseNames::Glob) => format!("use {names} from {from}"),
+            (Dialect::Legacy, UseNames::Names(_)) => format!("use {{{names}}} from {from}"),
+            (Dialect::Component, _) => format!("use {from}.{{{names}}};"),
+        };
+        Ok(format!("{pad}{res}\n"))
+    }
+
+    /// Print a function of a resource or interface with its docs
+    ///
+    /// In the legacy dialect, `&mut self` methods are prefixed with a `///@mutable` doc comment.
+    fn print_method(&self, func: &Function, depth: usize) -> Result<String> {
+        let pad = indent(depth);
+        let mut res = print_docs(&func.docs, depth);
+        if let (Dialect::Legacy, FunctionKind::Method { mutable: true }) = (self.dialect, func.kind)
+        {
+            writeln!(&mut res, "{pad}///@mutable")?;
+        }
+        let static_decl = match (self.dialect, func.kind) {
+            (
+                Dialect::Legacy,
+                FunctionKind::Static | FunctionKind::Constructor | FunctionKind::Consumer,
+            ) => "static ",
+            _ => "",
+        };
+        writeln!(&mut res, "{pad}{static_decl}{}", self.print_function(func)?)?;
+        Ok(res)
+    }
+
+    /// Print a function declaration without docs nor indentation
+    pub fn print_function(&self, func: &Function) -> Result<String> {
+        let params = func
+            .params
+            .iter()
+            .map(|param| {
+                let ty = self.print_type(&param.ty)?;
+                Ok(format!("{}: {ty}", self.ident(&param.name)))
+            })
+            .collect::<Result<Vec<String>>>()?
+            .join(", ");
+        let result = match &func.result {
+            // Component model functions without results simply omit the arrow
+            Some(ty) if ty.is_unit() && self.dialect == Dialect::Component => String::new(),
+            Some(ty) => format!(" -> {}", self.print_type(ty)?),
+            None => String::new(),
+        };
+        let term = self.terminator();
+        let name = self.ident(&func.name);
+        let res = match (self.dialect, func.kind) {
+            (Dialect::Component, FunctionKind::Constructor) => format!("constructor({params});"),
+            (Dialect::Component, FunctionKind::Static | FunctionKind::Consumer) => {
+                format!("{name}: static func({params}){result};")
+            }
+            _ => format!("{name}: func({params}){result}{term}"),
+        };
+        Ok(res)
+    }
+
+    pub fn print_type(&self, ty: &Type) -> Result<String> {
+        let legacy = self.dialect == Dialect::Legacy;
+        let res = match ty {
+            Type::Bool => "bool".to_string(),
+            Type::U8 => "u8".to_string(),
+            Type::U16 => "u16".to_string(),
+            Type::U32 => "u32".to_string(),
+            Type::U64 => "u64".to_string(),
+            Type::S8 => "s8".to_string(),
+            Type::S16 => "s16".to_string(),
+            Type::S32 => "s32".to_string(),
+            Type::S64 => "s64".to_string(),
+            Type::F32 if legacy => "float32".to_string(),
+            Type::F64 if legacy => "float64".to_string(),
+            Type::F32 => "f32".to_string(),
+            Type::F64 => "f64".to_string(),
+            Type::Char => "char".to_string(),
+            Type::String => "string".to_string(),
+            Type::List(ty) => format!("list<{}>", self.print_type(ty)?),
+            Type::Option(ty) => format!("option<{}>", self.print_type(ty)?),
+            Type::Result { ok, err } if legacy => match err {
+                Some(err) => format!(
+                    "expected<{}, {}>",
+                    self.print_type(ok)?,
+                    self.print_type(err)?
+                ),
+                None => format!("expected<{}>", self.print_type(ok)?),
+            },
+            Type::Result { ok, err } => match (ok.is_unit(), err) {
+                (true, None) => "result".to_string(),
+                // `result<_, E>` is how the component model spells a unit ok type
+                (true, Some(err)) => format!("result<_, {}>", self.print_type(err)?),
+                (false, None) => format!("result<{}>", self.print_type(ok)?),
+                (false, Some(err)) => {
+                    format!(
+                        "result<{}, {}>",
+                        self.print_type(ok)?,
+                        self.print_type(err)?
+                    )
+                }
+            },
+            Type::Tuple(types) => format!(
+                "tuple<{}>",
+                types
+                    .iter()
+                    .map(|ty| self.print_type(ty))
+                    .collect::<Result<Vec<String>>>()?
+                    .join(", ")
+            ),
+            Type::Named(name) => self.ident(name).into_owned(),
+            Type::Borrow(name) if legacy => self.ident(name).into_owned(),
+            Type::Borrow(name) => format!("borrow<{}>", self.ident(name)),
+            Type::Own(name) if legacy => self.ident(name).into_owned(),
+            Type::Own(name) => format!("own<{}>", self.ident(name)),
+        };
+        Ok(res)
+    }
+
+    /// Identifier escaped if it is a keyword of the dialect
+    fn ident<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        self.dialect.escape(name)
+    }
+
+    /// Component model declarations end with a semicolon
+    fn terminator(&self) -> &'static str {
+        match self.dialect {
+            Dialect::Legacy => "",
+            Dialect::Component => ";",
+        }
+    }
+}
+
+/// Interfaces of a component document, which tell where the types referenced by an interface or
+/// a world are declared
+struct Scope<'a> {
+    /// Name of the package interface
+    main: &'a str,
+    /// Types declared by the package interface
+    type_names: Vec<&'a str>,
+    /// `use`s of the package interface
+    imports: &'a [&'a Use],
+    interfaces: Vec<&'a Interface>,
+}
+
+impl Scope<'_> {
+    /// `use`s making the package types, including the ones the package interface imports, and
+    /// the types of the other interfaces visible to `items`, used by `user`
+    fn uses(&self, user: &str, interface: Option<&Interface>, items: &[Item]) -> Result<Vec<Use>> {
+        let mut uses: Vec<Use> = vec![];
+        let declared = interface.map(Interface::type_names).unwrap_or_default();
+        for name in items.iter().flat_map(Item::referenced_names) {
+            if declared.contains(&name.as_str()) {
+                continue;
+            }
+            let from = if self.type_names.contains(&name.as_str())
+                || self.imports.iter().any(|import| import.imports(&name))
+            {
+                self.main
+            } else {
+                let declaring: Vec<&str> = self
+                    .interfaces
+                    .iter()
+                    .filter(|other| {
+                        interface.map_or(true, |interface| other.name != interface.name)
+                    })
+                    .filter(|other| other.type_names().contains(&name.as_str()))
+                    .map(|other| other.name.as_str())
+                    .collect();
+                match declaring.as_slice() {
+                    [] => continue,
+                    [from] => *from,
+                    _ => bail!(
+                        "`{name}` used by {user} is declared by several interfaces: {}",
+                        declaring.join(", ")
+                    ),
+                }
+            };
+            match uses.iter_mut().find(|import| import.from == from) {
+                Some(import) => {
+                    if !import.imports(&name) {
+                        import.add(name);
+                    }
+                }
+                None => uses.push(component_use(from, vec![name])),
+            }
+        }
+        Ok(uses)
+    }
+}
+
+fn component_use(interface: &str, names: Vec<String>) -> Use {
+    Use {
+        docs: vec![],
+        from: interface.to_string(),
+        names: UseNames::Names(
+            names
+                .into_iter()
+                .map(|name| crate::ir::UseName { name, rename: None })
+                .collect(),
+        ),
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+pub(crate) fn print_docs(docs: &Docs, depth: usize) -> String {
+    let pad = indent(depth);
+    docs.iter()
+        .map(|line| format!("{pad}///{line}\n"))
+        .collect()
+}
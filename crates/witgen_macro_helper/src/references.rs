@@ -0,0 +1,192 @@
+//! Semantic check of the types referenced by the generated definitions.
+//!
+//! A Rust type which isn't annotated with `#[witgen]` isn't generated, references to it would
+//! be left dangling in the output.
+
+use anyhow::anyhow;
+use heck::ToKebabCase;
+use proc_macro2::Span;
+use std::collections::HashSet;
+use syn::{
+    spanned::Spanned,
+    visit::{self, Visit},
+    File, Item, TypePath,
+};
+
+use crate::{
+    attrs::is_witgen_attr,
+    diagnostics::{Diagnostic, Diagnostics},
+    generator::gen_wit_import,
+    ir::{Document, Item as WitItem, UseName, UseNames},
+    wit::declared_name,
+    Wit,
+};
+
+/// Report the references to types which are neither declared in `doc` nor in `prefix`, the wit
+/// the output starts with
+///
+/// Each reference is reported on the Rust item it appears in, along with the declaration of the
+/// type when it is found in `file` without `#[witgen]`. The names imported with a glob aren't
+/// known: a type which isn't declared by the crate isn't reported when a glob of the prefix or
+/// of its module could import it.
+pub fn check_references(file: &File, doc: &Document, prefix: &str) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    let mut declared: HashSet<String> =
+        doc.all_type_names().into_iter().map(String::from).collect();
+    let mut globs = false;
+    for item in &doc.items {
+        if let WitItem::Use(import) = item {
+            match &import.names {
+                UseNames::Glob => globs = true,
+                UseNames::Names(names) => declared.extend(imported_names(names)),
+            }
+        }
+    }
+    let mut prefix_glob = false;
+    for line in prefix.lines() {
+        match prefix_import(line) {
+            Some(UseNames::Glob) => prefix_glob = true,
+            Some(UseNames::Names(names)) => declared.extend(imported_names(&names)),
+            None => declared.extend(declared_name(line)),
+        }
+    }
+
+    let mut unresolved: Vec<String> = vec![];
+    for name in doc.items.iter().flat_map(WitItem::referenced_names) {
+        if !declared.contains(&name) && !unresolved.contains(&name) {
+            unresolved.push(name);
+        }
+    }
+    if unresolved.is_empty() {
+        return diagnostics;
+    }
+
+    let wit = Wit::from(file.clone());
+    // Modules with a `use` importing a glob, which is only in scope there
+    let mut glob_modules = vec![];
+    if globs {
+        wit.for_each_leaf(&mut vec![], &mut |item, module_path| {
+            if let Wit::Use(import) = item {
+                let uses = gen_wit_import(import).unwrap_or_default();
+                if uses.iter().any(|import| import.names == UseNames::Glob) {
+                    glob_modules.push(module_path.to_vec());
+                }
+            }
+        });
+    }
+    wit.for_each_leaf(&mut vec![], &mut |item, module_path| {
+        let mut visitor = ReferenceVisitor {
+            names: &unresolved,
+            found: vec![],
+        };
+        item.visit_types(&mut visitor);
+        for (name, span) in visitor.found {
+            let help = find_declaration(&file.items, &name, &mut vec![]);
+            if help.is_none()
+                && (prefix_glob || glob_modules.iter().any(|path| path == module_path))
+            {
+                continue;
+            }
+            let message = if help.is_some() {
+                format!("`{name}` isn't declared in wit")
+            } else {
+                format!("`{name}` isn't declared in wit, annotate it with `#[witgen]` or import it with `use`")
+            };
+            let mut diagnostic =
+                Diagnostic::new(item.name(), module_path.to_vec(), &anyhow!(message), span);
+            diagnostic.help = help.map(Box::new);
+            diagnostics.push(diagnostic);
+        }
+    });
+    diagnostics
+}
+
+/// Names `names` are imported under
+fn imported_names(names: &[UseName]) -> impl Iterator<Item = String> + '_ {
+    names
+        .iter()
+        .map(|name| name.rename.as_ref().unwrap_or(&name.name).clone())
+}
+
+/// Names imported by a `use` line of the prefix, in either dialect
+/// ```ts
+/// use { a, b as c } from dep
+/// use pkg:dep/types.{a, b as c};
+/// ```
+fn prefix_import(line: &str) -> Option<UseNames> {
+    let import = line.strip_prefix("use ")?;
+    if import.contains('*') {
+        return Some(UseNames::Glob);
+    }
+    let (_, names) = import.split_once('{')?;
+    let (names, _) = names.split_once('}')?;
+    let names = names
+        .split(',')
+        .filter_map(|name| {
+            let mut words = name
+                .split_whitespace()
+                .map(|word| word.trim_start_matches('%'));
+            let name = words.next()?.to_string();
+            let rename = match (words.next(), words.next()) {
+                (Some("as"), Some(rename)) => Some(rename.to_string()),
+                _ => None,
+            };
+            Some(UseName { name, rename })
+        })
+        .collect();
+    Some(UseNames::Names(names))
+}
+
+/// Declaration of the type named `name` in wit, which isn't annotated with `#[witgen]`
+fn find_declaration(
+    items: &[Item],
+    name: &str,
+    module_path: &mut Vec<String>,
+) -> Option<Diagnostic> {
+    for item in items {
+        let (ident, attrs) = match item {
+            Item::Struct(item) => (&item.ident, &item.attrs),
+            Item::Enum(item) => (&item.ident, &item.attrs),
+            Item::Type(item) => (&item.ident, &item.attrs),
+            Item::Mod(item) => {
+                if let Some((_, items)) = &item.content {
+                    module_path.push(item.ident.to_string());
+                    let found = find_declaration(items, name, module_path);
+                    module_path.pop();
+                    if found.is_some() {
+                        return found;
+                    }
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if ident.to_string().to_kebab_case() == name && !attrs.iter().any(is_witgen_attr) {
+            return Some(Diagnostic::new(
+                ident.to_string(),
+                module_path.clone(),
+                &anyhow!("add `#[witgen]` to generate it"),
+                ident.span(),
+            ));
+        }
+    }
+    None
+}
+
+/// Finds the paths to the types named `names` in wit
+struct ReferenceVisitor<'a> {
+    names: &'a [String],
+    found: Vec<(String, Span)>,
+}
+
+impl<'ast> Visit<'ast> for ReferenceVisitor<'_> {
+    fn visit_type_path(&mut self, path: &'ast TypePath) {
+        if let Some(segment) = path.path.segments.last() {
+            let name = segment.ident.to_string().to_kebab_case();
+            if self.names.contains(&name) {
+                self.found.push((name, path.span()));
+            }
+        }
+        visit::visit_type_path(self, path);
+    }
+}
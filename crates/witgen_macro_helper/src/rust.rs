@@ -0,0 +1,380 @@
+//! Rust definitions of wit documents, the reverse of the generators.
+//!
+//! Every item is annotated with `#[witgen]`, and with `#[witgen(rename = "...")]` where the
+//! Rust name wouldn't give back the wit one, so that generating wit from the printed source
+//! reproduces the document.
+
+use anyhow::{bail, Result};
+use heck::{ToKebabCase, ToSnakeCase, ToUpperCamelCase};
+use std::fmt::Write;
+
+use crate::{
+    ir::{
+        Docs, Document, Enum, Flags, Function, FunctionKind, Interface, Item, Record, Resource,
+        Type, TypeAlias, Use, UseNames, Variant,
+    },
+    util::wit_ident,
+};
+
+/// Keywords which can't be used as identifiers, even raw ones
+const RESERVED: &[&str] = &["crate", "self", "Self", "super"];
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+/// Types the generators give a meaning of their own, see [`crate::wit::ToWitType`]
+const SPECIAL_TYPES: &[&str] = &[
+    "Arc", "Box", "Cow", "HashMap", "Option", "Rc", "Result", "Self", "String", "Vec",
+];
+
+/// Renders the wit intermediate representation as Rust source
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RustPrinter;
+
+impl RustPrinter {
+    /// Print a whole document, items being separated by blank lines
+    /// ```ts
+    /// record foo-bar {
+    ///   a: option<s32>
+    /// }
+    ///
+    /// get: func(id: u64) -> foo-bar
+    /// ```
+    /// becomes
+    /// ```rust,ignore
+    /// use witgen::witgen;
+    ///
+    /// #[witgen]
+    /// pub struct FooBar {
+    ///     pub a: Option<i32>,
+    /// }
+    ///
+    /// #[witgen]
+    /// pub fn get(id: u64) -> FooBar {
+    ///     todo!()
+    /// }
+    /// ```
+    ///
+    /// Functions are stubs to fill in, resources are a struct along with an `impl` of stubs
+    /// and interfaces are traits.
+    pub fn print_document(&self, doc: &Document) -> Result<String> {
+        let mut res = "use witgen::witgen;\n".to_string();
+        for item in &doc.items {
+            write!(&mut res, "\n{}", self.print_item(item)?)?;
+        }
+        Ok(res)
+    }
+
+    pub fn print_item(&self, item: &Item) -> Result<String> {
+        let docs = print_docs(item.docs(), 0);
+        let res = match item {
+            Item::Use(import) => print_use(import),
+            Item::Record(Record { name, fields, .. }) => {
+                let (ident, rename) = type_ident(name);
+                let mut res = format!("{}pub struct {ident} {{\n", witgen_attr(&[], rename));
+                for field in fields {
+                    let (field_ident, rename) = value_ident(&field.name);
+                    writeln!(
+                        &mut res,
+                        "{}{}    pub {field_ident}: {},",
+                        print_docs(&field.docs, 1),
+                        arg_attr(rename, "    "),
+                        print_type(&field.ty, None)
+                    )?;
+                }
+                res.push_str("}\n");
+                res
+            }
+            Item::Flags(Flags { name, flags, .. }) => {
+                let (ident, rename) = type_ident(name);
+                let mut res = format!("{}pub struct {ident} {{\n", witgen_attr(&["flags"], rename));
+                for flag in flags {
+                    let (flag_ident, rename) = value_ident(&flag.name);
+                    writeln!(
+                        &mut res,
+                        "{}{}    pub {flag_ident}: bool,",
+                        print_docs(&flag.docs, 1),
+                        arg_attr(rename, "    "),
+                    )?;
+                }
+                res.push_str("}\n");
+                res
+            }
+            Item::Variant(Variant { name, cases, .. }) => {
+                let (ident, rename) = type_ident(name);
+                let mut res = format!("{}pub enum {ident} {{\n", witgen_attr(&[], rename));
+                for case in cases {
+                    let (case_ident, rename) = type_ident(&case.name);
+                    let payload = match &case.ty {
+                        // Several fields are generated as a tuple, keep shorter ones as is
+                        Some(Type::Tuple(types)) if types.len() > 1 => {
+                            format!("({})", print_types(types, None))
+                        }
+                        Some(ty) => format!("({})", print_type(ty, None)),
+                        None => String::new(),
+                    };
+                    writeln!(
+                        &mut res,
+                        "{}{}    {case_ident}{payload},",
+                        print_docs(&case.docs, 1),
+                        arg_attr(rename, "    "),
+                    )?;
+                }
+                res.push_str("}\n");
+                res
+            }
+            Item::Enum(Enum { name, cases, .. }) => {
+                let (ident, rename) = type_ident(name);
+                let mut res = format!("{}pub enum {ident} {{\n", witgen_attr(&[], rename));
+                for case in cases {
+                    let (case_ident, rename) = type_ident(&case.name);
+                    writeln!(
+                        &mut res,
+                        "{}{}    {case_ident},",
+                        print_docs(&case.docs, 1),
+                        arg_attr(rename, "    "),
+                    )?;
+                }
+                res.push_str("}\n");
+                res
+            }
+            Item::Alias(TypeAlias { name, ty, .. }) => {
+                let (ident, rename) = type_ident(name);
+                format!(
+                    "{}pub type {ident} = {};\n",
+                    witgen_attr(&[], rename),
+                    print_type(ty, None)
+                )
+            }
+            Item::Function(func) => {
+                let (ident, rename) = value_ident(&func.name);
+                format!(
+                    "{}pub fn {ident}({}){} {{\n    todo!()\n}}\n",
+                    witgen_attr(&[], rename),
+                    print_params(func, None),
+                    print_result(func, None)
+                )
+            }
+            Item::Resource(Resource {
+                name, functions, ..
+            }) => {
+                let (ident, rename) = type_ident(name);
+                let methods = functions
+                    .iter()
+                    .map(|func| self.print_method(func, name))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                // The struct itself isn't generated, only its `impl` is, which has the docs
+                return Ok(format!(
+                    "pub struct {ident};\n\n{docs}{}impl {ident} {{\n{methods}}}\n",
+                    witgen_attr(&[], rename)
+                ));
+            }
+            Item::Interface(Interface { name, items, .. }) => {
+                let (ident, rename) = type_ident(name);
+                let mut res = format!("{}pub trait {ident} {{\n", witgen_attr(&[], rename));
+                for item in items {
+                    let mut func = match item {
+                        Item::Function(func) => func.clone(),
+                        _ => bail!("`{name}`: only functions can be imported in an interface"),
+                    };
+                    // Functions of a trait take `&self`, which isn't part of their signature
+                    if func.kind == FunctionKind::Freestanding {
+                        func.kind = FunctionKind::Method { mutable: false };
+                    }
+                    let (fn_ident, rename) = value_ident(&func.name);
+                    writeln!(
+                        &mut res,
+                        "{}{}    fn {fn_ident}({}){};",
+                        print_docs(&func.docs, 1),
+                        arg_attr(rename, "    "),
+                        print_params(&func, None),
+                        print_result(&func, None)
+                    )?;
+                }
+                res.push_str("}\n");
+                res
+            }
+        };
+        Ok(format!("{docs}{res}"))
+    }
+
+    /// Public method of a resource's `impl`, where the resource is `Self`
+    fn print_method(&self, func: &Function, resource: &str) -> String {
+        let (ident, rename) = value_ident(&func.name);
+        let result = match func.kind {
+            FunctionKind::Constructor => " -> Self".to_string(),
+            _ => print_result(func, Some(resource)),
+        };
+        format!(
+            "{}{}    pub fn {ident}({}){result} {{\n        todo!()\n    }}\n",
+            print_docs(&func.docs, 1),
+            arg_attr(rename, "    "),
+            print_params(func, Some(resource)),
+        )
+    }
+}
+
+fn print_use(import: &Use) -> String {
+    let from = import.from.to_snake_case();
+    let names = match &import.names {
+        UseNames::Glob => "*".to_string(),
+        UseNames::Names(names) => {
+            let names = names
+                .iter()
+                .map(|name| match &name.rename {
+                    Some(rename) => {
+                        format!("{} as {}", type_ident(&name.name).0, type_ident(rename).0)
+                    }
+                    None => type_ident(&name.name).0,
+                })
+                .collect::<Vec<String>>();
+            match names.as_slice() {
+                [name] => name.clone(),
+                names => format!("{{{}}}", names.join(", ")),
+            }
+        }
+    };
+    format!("#[witgen]\nuse {from}::{names};\n")
+}
+
+/// `&self` and the arguments of a function, the owned handle of a consumer being its `self`
+fn print_params(func: &Function, self_name: Option<&str>) -> String {
+    let (receiver, params) = match func.kind {
+        FunctionKind::Method { mutable: true } => (Some("&mut self"), &func.params[..]),
+        FunctionKind::Method { mutable: false } => (Some("&self"), &func.params[..]),
+        FunctionKind::Consumer => (Some("self"), func.params.get(1..).unwrap_or_default()),
+        FunctionKind::Freestanding | FunctionKind::Static | FunctionKind::Constructor => {
+            (None, &func.params[..])
+        }
+    };
+    receiver
+        .map(str::to_string)
+        .into_iter()
+        .chain(params.iter().map(|param| {
+            let (ident, rename) = value_ident(&param.name);
+            let attr = match rename {
+                Some(rename) => format!("#[witgen(rename = {rename:?})] "),
+                None => String::new(),
+            };
+            format!("{attr}{ident}: {}", print_type(&param.ty, self_name))
+        }))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn print_result(func: &Function, self_name: Option<&str>) -> String {
+    match &func.result {
+        Some(ty) => format!(" -> {}", print_type(ty, self_name)),
+        None => String::new(),
+    }
+}
+
+/// Rust type generating `ty`, where `self_name` is `Self`
+fn print_type(ty: &Type, self_name: Option<&str>) -> String {
+    match ty {
+        Type::Bool => "bool".to_string(),
+        Type::U8 => "u8".to_string(),
+        Type::U16 => "u16".to_string(),
+        Type::U32 => "u32".to_string(),
+        Type::U64 => "u64".to_string(),
+        Type::S8 => "i8".to_string(),
+        Type::S16 => "i16".to_string(),
+        Type::S32 => "i32".to_string(),
+        Type::S64 => "i64".to_string(),
+        Type::F32 => "f32".to_string(),
+        Type::F64 => "f64".to_string(),
+        Type::Char => "char".to_string(),
+        Type::String => "String".to_string(),
+        Type::List(ty) => format!("Vec<{}>", print_type(ty, self_name)),
+        Type::Option(ty) => format!("Option<{}>", print_type(ty, self_name)),
+        Type::Result { ok, err: Some(err) } => format!(
+            "Result<{}, {}>",
+            print_type(ok, self_name),
+            print_type(err, self_name)
+        ),
+        Type::Result { ok, err: None } => format!("Result<{}>", print_type(ok, self_name)),
+        Type::Tuple(types) if types.len() == 1 => {
+            format!("({},)", print_type(&types[0], self_name))
+        }
+        Type::Tuple(types) => format!("({})", print_types(types, self_name)),
+        Type::Named(name) if Some(name.as_str()) == self_name => "Self".to_string(),
+        Type::Named(name) => type_ident(name).0,
+        Type::Borrow(name) if Some(name.as_str()) == self_name => "&Self".to_string(),
+        Type::Borrow(name) => format!("&{}", type_ident(name).0),
+        Type::Own(name) if Some(name.as_str()) == self_name => "Self".to_string(),
+        Type::Own(name) => type_ident(name).0,
+    }
+}
+
+fn print_types(types: &[Type], self_name: Option<&str>) -> String {
+    types
+        .iter()
+        .map(|ty| print_type(ty, self_name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Rust name of a type, enum variant or resource, along with the wit name to give it with
+/// `rename` when the kebab-cased Rust name is a different one
+fn type_ident(name: &str) -> (String, Option<&str>) {
+    let mut ident = name.to_upper_camel_case();
+    if SPECIAL_TYPES.contains(&ident.as_str()) {
+        ident.push_str("Type");
+    }
+    let rename = if ident.to_kebab_case() != name {
+        Some(name)
+    } else {
+        None
+    };
+    (ident, rename)
+}
+
+/// Rust name of a field, function or argument, see [`type_ident`]
+fn value_ident(name: &str) -> (String, Option<&str>) {
+    let mut ident = name.to_snake_case();
+    if RESERVED.contains(&ident.as_str()) {
+        ident.push('_');
+    } else if KEYWORDS.contains(&ident.as_str()) {
+        ident.insert_str(0, "r#");
+    }
+    let rename = if wit_ident(&ident) == name {
+        None
+    } else {
+        Some(name)
+    };
+    (ident, rename)
+}
+
+/// `#[witgen]` with the given arguments, on a line of its own
+fn witgen_attr(args: &[&str], rename: Option<&str>) -> String {
+    let mut args: Vec<String> = args.iter().map(ToString::to_string).collect();
+    if let Some(rename) = rename {
+        args.push(format!("rename = {rename:?}"));
+    }
+    if args.is_empty() {
+        "#[witgen]\n".to_string()
+    } else {
+        format!("#[witgen({})]\n", args.join(", "))
+    }
+}
+
+/// `#[witgen(rename = "...")]` on a line of its own before a field, variant or method
+fn arg_attr(rename: Option<&str>, indent: &str) -> String {
+    match rename {
+        Some(rename) => format!("{indent}#[witgen(rename = {rename:?})]\n"),
+        None => String::new(),
+    }
+}
+
+fn print_docs(docs: &Docs, depth: usize) -> String {
+    let pad = "    ".repeat(depth);
+    docs.iter()
+        .map(|line| format!("{pad}///{line}\n"))
+        .collect()
+}
@@ -1,13 +1,22 @@
 use std::fmt::Display;
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use heck::ToKebabCase;
+use proc_macro2::Span;
+use quote::ToTokens;
 use syn::{FnArg, ImplItem, ImplItemMethod, PatType, ReturnType, Signature, Type, Visibility};
 
-use crate::wit::ToWitType;
+use crate::{
+    attrs::wit_name,
+    ir::{Param, Type as WitType},
+    wit::ToWitType,
+};
 
 pub enum FuncType {
+    /// Takes `&self`, or `&mut self` when `true`
     Instance(bool),
+    /// Takes `self` by value
+    Consuming,
     Standalone,
 }
 
@@ -22,15 +31,16 @@ pub fn non_receiver_args(fn_arg: &FnArg) -> Option<&PatType> {
 pub trait SignatureUtils {
     fn fn_type(&self) -> FuncType;
 
-    fn fn_args(&self) -> Result<Vec<String>>;
+    fn fn_args(&self) -> Result<Vec<Param>>;
 
-    fn ret_args(&self) -> Result<String>;
+    fn ret_args(&self) -> Result<Option<WitType>>;
 }
 
 impl SignatureUtils for Signature {
     fn fn_type(&self) -> FuncType {
         for fn_arg in self.inputs.iter() {
             match fn_arg {
+                FnArg::Receiver(r) if r.reference.is_none() => return FuncType::Consuming,
                 FnArg::Receiver(r) => return FuncType::Instance(r.mutability.is_some()),
                 FnArg::Typed(_) => continue,
             }
@@ -38,89 +48,60 @@ impl SignatureUtils for Signature {
         FuncType::Standalone
     }
 
-    fn fn_args(&self) -> Result<Vec<String>> {
+    fn fn_args(&self) -> Result<Vec<Param>> {
         self.inputs
             .iter()
             .filter_map(non_receiver_args)
             .map(|typed_pat| {
-                let pat = match &*typed_pat.pat {
-                    syn::Pat::Ident(ident) => wit_ident(&ident.ident)?,
-                    _ => bail!("can't handle this kind of fn argument"),
+                let name = match &*typed_pat.pat {
+                    syn::Pat::Ident(ident) => wit_name(&ident.ident, &typed_pat.attrs)?,
+                    pat => return Err(spanned_error(pat, "can't handle this kind of fn argument")),
                 };
-                let ty = typed_pat.ty.to_wit()?;
-                Ok(format!("{}: {}", pat, ty))
+                let ty = param_type(&typed_pat.ty)?;
+                Ok(Param { name, ty })
             })
-            .collect::<Result<Vec<String>>>()
+            .collect::<Result<Vec<Param>>>()
     }
 
-    fn ret_args(&self) -> Result<String> {
-        let res = if let ReturnType::Type(_, return_ty) = &self.output {
-            if let Type::Tuple(tuple) = return_ty.as_ref() {
-                let tuple_fields = tuple
-                    .elems
-                    .iter()
-                    .map(|f| f.to_wit())
-                    .collect::<Result<Vec<String>>>()?
-                    .join(", ");
-                format!(" -> tuple<{}>", tuple_fields)
-            } else {
-                format!(" -> {}", return_ty.to_wit()?)
-            }
-        } else {
-            "".to_string()
-        };
-        Ok(res)
+    fn ret_args(&self) -> Result<Option<WitType>> {
+        match &self.output {
+            ReturnType::Type(_, return_ty) => Ok(Some(return_ty.to_wit()?)),
+            ReturnType::Default => Ok(None),
+        }
     }
 }
 
-pub fn wit_ident<T: Display + ?Sized>(ident: &T) -> Result<String> {
-    is_known_keyword(ident.to_string().to_kebab_case())
+/// Wit type of a parameter, where references to declared types are borrowed handles
+///
+/// Only resources can be borrowed, [`crate::ir::Document::resolve_handles`] turns the other
+/// ones back into plain references once the resources are known.
+pub(crate) fn param_type(ty: &Type) -> Result<WitType> {
+    match ty {
+        Type::Reference(reference) => match reference.elem.to_wit()? {
+            WitType::Named(name) => Ok(WitType::Borrow(name)),
+            ty => Ok(ty),
+        },
+        ty => ty.to_wit(),
+    }
 }
 
-pub(crate) fn is_known_keyword(ident: String) -> Result<String> {
-    if matches!(
-        ident.as_str(),
-        "use"
-            | "type"
-            | "resource"
-            | "func"
-            | "u8"
-            | "u16"
-            | "u32"
-            | "u64"
-            | "s8"
-            | "s16"
-            | "s32"
-            | "s64"
-            | "float32"
-            | "float64"
-            | "char"
-            | "handle"
-            | "record"
-            | "enum"
-            | "flags"
-            | "variant"
-            | "union"
-            | "bool"
-            | "string"
-            | "option"
-            | "list"
-            | "expected"
-            | "_"
-            | "as"
-            | "from"
-            | "static"
-            | "interface"
-            | "tuple"
-            | "async"
-    ) {
-        Err(anyhow::anyhow!(
-            "'{}' is a known keyword you can't use the same identifier",
-            ident
-        ))
-    } else {
-        Ok(ident)
-    }
+/// Error pointing at the tokens it is about, so that it can be reported with their location
+pub(crate) fn spanned_error<T: ToTokens, M: Display>(tokens: T, message: M) -> anyhow::Error {
+    syn::Error::new_spanned(tokens, message).into()
+}
+
+/// Convert an error to a `syn::Error`, keeping its span or falling back to `span`
+pub(crate) fn to_syn_error(err: anyhow::Error, span: Span) -> syn::Error {
+    err.downcast::<syn::Error>()
+        .unwrap_or_else(|err| syn::Error::new(span, err))
+}
+
+/// Kebab-cased wit name of a Rust identifier
+///
+/// Keywords are escaped when printed, see [`crate::Dialect::escape`].
+pub fn wit_ident<T: Display + ?Sized>(ident: &T) -> String {
+    let ident = ident.to_string();
+    ident.trim_start_matches("r#").to_kebab_case()
 }
 
 pub fn pub_method(item: &ImplItem) -> Option<&ImplItemMethod> {
@@ -1,5 +1,12 @@
-use syn::visit_mut::VisitMut;
-use syn::{parse_quote, ImplItemMethod, ItemImpl, Meta};
+use proc_macro2::Ident;
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    parse_quote, Attribute, Field, FieldsNamed, FieldsUnnamed, File, GenericParam, Generics,
+    ImplItem, ImplItemMethod, Item, ItemEnum, ItemImpl, ItemMod, ItemStruct, ItemTrait, ItemType,
+    Meta, PatType, Signature, TraitItem, TraitItemMethod, Type, Variant,
+};
+
+use crate::{is_witgen_attr, Cfg};
 
 pub struct ImplVisitor;
 
@@ -27,3 +34,266 @@ impl VisitMut for ImplVisitor {
             })
     }
 }
+
+/// Replaces `Self` in a method signature by the type the `impl` block is for
+pub struct SelfTypeVisitor<'a> {
+    self_ty: &'a Type,
+}
+
+impl<'a> SelfTypeVisitor<'a> {
+    pub fn replace_self(sig: &Signature, self_ty: &'a Type) -> Signature {
+        let mut sig = sig.clone();
+        SelfTypeVisitor { self_ty }.visit_signature_mut(&mut sig);
+        sig
+    }
+}
+
+impl VisitMut for SelfTypeVisitor<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        match ty {
+            Type::Path(path) if path.qself.is_none() && path.path.is_ident("Self") => {
+                *ty = self.self_ty.clone();
+            }
+            _ => visit_mut::visit_type_mut(self, ty),
+        }
+    }
+}
+
+/// Replaces the type parameters of a generic item by concrete types
+pub struct TypeParamVisitor {
+    params: Vec<(Ident, Type)>,
+}
+
+impl TypeParamVisitor {
+    pub fn new(params: impl IntoIterator<Item = (Ident, Type)>) -> Self {
+        Self {
+            params: params.into_iter().collect(),
+        }
+    }
+
+    pub fn substitute_struct(&mut self, item: &ItemStruct) -> ItemStruct {
+        let mut item = item.clone();
+        strip_type_params(&mut item.generics);
+        self.visit_item_struct_mut(&mut item);
+        item
+    }
+
+    pub fn substitute_enum(&mut self, item: &ItemEnum) -> ItemEnum {
+        let mut item = item.clone();
+        strip_type_params(&mut item.generics);
+        self.visit_item_enum_mut(&mut item);
+        item
+    }
+
+    pub fn substitute_type(&mut self, item: &ItemType) -> ItemType {
+        let mut item = item.clone();
+        strip_type_params(&mut item.generics);
+        self.visit_item_type_mut(&mut item);
+        item
+    }
+}
+
+/// Keep only the lifetimes of `generics`
+fn strip_type_params(generics: &mut Generics) {
+    generics.params = generics
+        .params
+        .iter()
+        .filter(|param| matches!(param, GenericParam::Lifetime(_)))
+        .cloned()
+        .collect();
+    generics.where_clause = None;
+}
+
+impl VisitMut for TypeParamVisitor {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(path) = ty {
+            if let Some(ident) = path.path.get_ident().filter(|_| path.qself.is_none()) {
+                if let Some((_, concrete)) = self.params.iter().find(|(param, _)| param == ident) {
+                    *ty = concrete.clone();
+                    return;
+                }
+            }
+        }
+        visit_mut::visit_type_mut(self, ty);
+    }
+}
+
+/// Removes the `#[witgen(...)]` annotations of fields, variants, fn arguments and methods,
+/// which are only read by witgen and unknown to the compiler
+pub struct WitgenAttrVisitor;
+
+impl WitgenAttrVisitor {
+    pub fn strip_attrs(item: &mut Item) {
+        WitgenAttrVisitor.visit_item_mut(item);
+    }
+
+    fn strip(attrs: &mut Vec<Attribute>) {
+        attrs.retain(|attr| !is_witgen_attr(attr));
+    }
+}
+
+impl VisitMut for WitgenAttrVisitor {
+    fn visit_field_mut(&mut self, field: &mut Field) {
+        Self::strip(&mut field.attrs);
+        visit_mut::visit_field_mut(self, field);
+    }
+
+    fn visit_variant_mut(&mut self, variant: &mut Variant) {
+        Self::strip(&mut variant.attrs);
+        visit_mut::visit_variant_mut(self, variant);
+    }
+
+    fn visit_pat_type_mut(&mut self, pat: &mut PatType) {
+        Self::strip(&mut pat.attrs);
+        visit_mut::visit_pat_type_mut(self, pat);
+    }
+
+    fn visit_impl_item_method_mut(&mut self, method: &mut ImplItemMethod) {
+        Self::strip(&mut method.attrs);
+        visit_mut::visit_impl_item_method_mut(self, method);
+    }
+
+    fn visit_trait_item_method_mut(&mut self, method: &mut TraitItemMethod) {
+        Self::strip(&mut method.attrs);
+        visit_mut::visit_trait_item_method_mut(self, method);
+    }
+}
+
+/// Removes the items, fields, variants and methods disabled by `#[cfg(...)]` and expands
+/// `#[cfg_attr(...)]`
+pub struct CfgVisitor<'a> {
+    cfg: &'a Cfg,
+    /// First error met, the visitor can't return early
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> CfgVisitor<'a> {
+    pub fn strip(file: &mut File, cfg: &'a Cfg) -> anyhow::Result<()> {
+        let mut visitor = CfgVisitor { cfg, error: None };
+        visitor.visit_file_mut(file);
+        visitor.error.map_or(Ok(()), Err)
+    }
+
+    /// Expand the `cfg_attr`s of `attrs` and tell whether the item they annotate is enabled
+    fn is_enabled(&mut self, attrs: &mut Vec<Attribute>) -> bool {
+        let res = self
+            .cfg
+            .expand_cfg_attrs(attrs)
+            .and_then(|_| self.cfg.is_enabled(attrs));
+        match res {
+            Ok(enabled) => enabled,
+            Err(err) => {
+                self.error.get_or_insert(err);
+                true
+            }
+        }
+    }
+
+    fn retain_items(&mut self, items: &mut Vec<Item>) {
+        *items = std::mem::take(items)
+            .into_iter()
+            .filter_map(|mut item| match item_attrs_mut(&mut item) {
+                Some(attrs) => self.is_enabled(attrs).then(|| item),
+                None => Some(item),
+            })
+            .collect();
+    }
+}
+
+fn item_attrs_mut(item: &mut Item) -> Option<&mut Vec<Attribute>> {
+    Some(match item {
+        Item::Const(item) => &mut item.attrs,
+        Item::Enum(item) => &mut item.attrs,
+        Item::ExternCrate(item) => &mut item.attrs,
+        Item::Fn(item) => &mut item.attrs,
+        Item::ForeignMod(item) => &mut item.attrs,
+        Item::Impl(item) => &mut item.attrs,
+        Item::Macro(item) => &mut item.attrs,
+        Item::Macro2(item) => &mut item.attrs,
+        Item::Mod(item) => &mut item.attrs,
+        Item::Static(item) => &mut item.attrs,
+        Item::Struct(item) => &mut item.attrs,
+        Item::Trait(item) => &mut item.attrs,
+        Item::TraitAlias(item) => &mut item.attrs,
+        Item::Type(item) => &mut item.attrs,
+        Item::Union(item) => &mut item.attrs,
+        Item::Use(item) => &mut item.attrs,
+        _ => return None,
+    })
+}
+
+impl VisitMut for CfgVisitor<'_> {
+    fn visit_file_mut(&mut self, file: &mut File) {
+        self.retain_items(&mut file.items);
+        visit_mut::visit_file_mut(self, file);
+    }
+
+    fn visit_item_mod_mut(&mut self, item: &mut ItemMod) {
+        if let Some((_, items)) = &mut item.content {
+            self.retain_items(items);
+        }
+        visit_mut::visit_item_mod_mut(self, item);
+    }
+
+    fn visit_item_impl_mut(&mut self, item: &mut ItemImpl) {
+        item.items = std::mem::take(&mut item.items)
+            .into_iter()
+            .filter_map(|mut item| {
+                let attrs = match &mut item {
+                    ImplItem::Method(method) => &mut method.attrs,
+                    ImplItem::Const(item) => &mut item.attrs,
+                    ImplItem::Type(item) => &mut item.attrs,
+                    _ => return Some(item),
+                };
+                self.is_enabled(attrs).then(|| item)
+            })
+            .collect();
+        visit_mut::visit_item_impl_mut(self, item);
+    }
+
+    fn visit_item_trait_mut(&mut self, item: &mut ItemTrait) {
+        item.items = std::mem::take(&mut item.items)
+            .into_iter()
+            .filter_map(|mut item| {
+                let attrs = match &mut item {
+                    TraitItem::Method(method) => &mut method.attrs,
+                    TraitItem::Const(item) => &mut item.attrs,
+                    TraitItem::Type(item) => &mut item.attrs,
+                    _ => return Some(item),
+                };
+                self.is_enabled(attrs).then(|| item)
+            })
+            .collect();
+        visit_mut::visit_item_trait_mut(self, item);
+    }
+
+    fn visit_fields_named_mut(&mut self, fields: &mut FieldsNamed) {
+        fields.named = fields
+            .named
+            .clone()
+            .into_iter()
+            .filter_map(|mut field| self.is_enabled(&mut field.attrs).then(|| field))
+            .collect();
+        visit_mut::visit_fields_named_mut(self, fields);
+    }
+
+    fn visit_fields_unnamed_mut(&mut self, fields: &mut FieldsUnnamed) {
+        fields.unnamed = fields
+            .unnamed
+            .clone()
+            .into_iter()
+            .filter_map(|mut field| self.is_enabled(&mut field.attrs).then(|| field))
+            .collect();
+        visit_mut::visit_fields_unnamed_mut(self, fields);
+    }
+
+    fn visit_item_enum_mut(&mut self, item: &mut ItemEnum) {
+        item.variants = item
+            .variants
+            .clone()
+            .into_iter()
+            .filter_map(|mut variant| self.is_enabled(&mut variant.attrs).then(|| variant))
+            .collect();
+        visit_mut::visit_item_enum_mut(self, item);
+    }
+}
@@ -1,23 +1,36 @@
 use anyhow::{bail, Result};
-use quote::ToTokens;
+use heck::ToKebabCase;
+use proc_macro2::{Ident, Span};
+use quote::{quote, ToTokens};
 use std::fmt::Display;
 use std::str::FromStr;
 use syn::{
-    parse2 as parse, Attribute, File, Item, ItemEnum, ItemFn, ItemImpl, ItemMod, ItemStruct,
-    ItemTrait, ItemType, ItemUse, Type as SynType, TypeReference,
+    ext::IdentExt, parse2 as parse, spanned::Spanned, visit::Visit, Attribute, File, FnArg, Item,
+    ItemEnum, ItemFn, ItemImpl, ItemMacro, ItemMod, ItemStruct, ItemTrait, ItemType, ItemUse,
+    ReturnType, Signature, TraitItem, Type as SynType, TypeReference,
 };
 
 use crate::{
+    attrs::{is_witgen_attr, wit_name, WitgenAttrs},
+    diagnostics::{error_location, Diagnostic, Diagnostics},
     generator::{
-        gen_wit_enum, gen_wit_function, gen_wit_impl, gen_wit_import, gen_wit_struct,
-        gen_wit_trait, gen_wit_type_alias, get_doc_comment,
+        gen_wit_bitflags, gen_wit_enum, gen_wit_function, gen_wit_impl, gen_wit_import,
+        gen_wit_struct, gen_wit_trait, gen_wit_type_alias, get_doc_comment, is_bitflags,
+        parse_bitflags,
     },
-    util::wit_ident,
+    generics::{instance_name, type_args, type_params, Monomorphizer},
+    ir::{Document, Interface, Item as WitItem, Type as WitType, Use, WorldItem},
+    printer::{print_docs, Printer},
+    util::{pub_method, spanned_error, to_syn_error, wit_ident},
+    Dialect,
 };
 
 /// Wit type that correspond to Rust Types using `syn`'s representation
 pub enum Wit {
-    Mod(Vec<Wit>, Vec<Attribute>),
+    /// Items of a module, its attributes and its name (`None` for the crate root)
+    ///
+    /// A module annotated with `#[witgen]` is an interface, see [`Wit::module_interface`].
+    Mod(Vec<Wit>, Vec<Attribute>, Option<Ident>),
     Record(ItemStruct),
     Function(ItemFn),
     Variant(ItemEnum),
@@ -25,6 +38,8 @@ pub enum Wit {
     Use(ItemUse),
     Interface(ItemTrait),
     Resource(ItemImpl),
+    /// `bitflags! { .. }` invocation
+    Flags(ItemMacro),
 }
 
 impl Wit {
@@ -41,31 +56,740 @@ impl Wit {
             Wit::Function(item) => Some(&item.attrs),
             Wit::Variant(item) => Some(&item.attrs),
             Wit::Type(item) => Some(&item.attrs),
-            Wit::Mod(_, attrs) => Some(attrs),
+            Wit::Mod(_, attrs, _) => Some(attrs),
             Wit::Use(item) => Some(&item.attrs),
             Wit::Resource(item) => Some(&item.attrs),
             Wit::Interface(item) => Some(&item.attrs),
+            Wit::Flags(item) => Some(&item.attrs),
         }
     }
 
     pub fn get_doc(&self) -> Result<String> {
-        get_doc_comment(self.attrs().unwrap_or_default(), 0, false)
+        let docs = get_doc_comment(self.attrs().unwrap_or_default(), false)?;
+        Ok(print_docs(&docs, 0))
+    }
+
+    /// Leaf items of this tree, i.e. the content of nested `Wit::Mod`s
+    pub fn flatten(&self) -> Vec<&Wit> {
+        match self {
+            Wit::Mod(wit, _, _) => wit.iter().flat_map(Wit::flatten).collect(),
+            other => vec![other],
+        }
+    }
+
+    /// Imports of this tree with `use`
+    ///
+    /// `use` items which can't be converted are left out, they are reported by
+    /// [`Wit::to_document`].
+    pub fn dependencies(&self) -> Vec<Use> {
+        self.flatten()
+            .into_iter()
+            .filter_map(|item| match item {
+                Wit::Use(item) => gen_wit_import(item).ok(),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Convert this item into wit definitions
+    pub fn to_items(&self) -> Result<Vec<WitItem>> {
+        let items = match self {
+            Wit::Mod(wit, attrs, _) => {
+                let mut items = vec![];
+                for item in wit {
+                    items.extend(item.to_items()?);
+                }
+                match self.module_interface()? {
+                    Some(name) => vec![WitItem::Interface(Interface {
+                        name,
+                        docs: get_doc_comment(attrs, false)?,
+                        items,
+                    })],
+                    None => items,
+                }
+            }
+            Wit::Record(item) => vec![gen_wit_struct(item)?],
+            Wit::Function(item) => vec![WitItem::Function(gen_wit_function(item)?)],
+            Wit::Variant(item) => gen_wit_enum(item)?,
+            Wit::Type(item) => vec![WitItem::Alias(gen_wit_type_alias(item)?)],
+            Wit::Use(item) => gen_wit_import(item)?
+                .into_iter()
+                .map(WitItem::Use)
+                .collect(),
+            Wit::Resource(item) => vec![WitItem::Resource(gen_wit_impl(item)?)],
+            Wit::Interface(item) => vec![WitItem::Interface(gen_wit_trait(item)?)],
+            Wit::Flags(item) => gen_wit_bitflags(item)?,
+        };
+        // Generic items are only generated once instantiated, and aliases given a `name` name
+        // an instantiation instead of being declared, see `Wit::to_document`
+        if self.is_template() || self.name_override()?.is_some() {
+            return Ok(vec![]);
+        }
+        Ok(items)
+    }
+
+    /// Name of the interface generated by a module annotated with `#[witgen]`, the kebab-cased
+    /// module name unless renamed
+    ///
+    /// The items of the crate root and of the other modules are generated in the enclosing
+    /// interface.
+    pub fn module_interface(&self) -> Result<Option<String>> {
+        match self {
+            Wit::Mod(_, attrs, Some(ident)) if has_witgen_macro(&Some(attrs.as_slice())) => {
+                Ok(Some(wit_name(ident, attrs)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// World this item is imported into or exported from, `None` standing for the default world,
+    /// and whether it is exported
+    ///
+    /// Set with `#[witgen(import)]`, `#[witgen(export)]` or `#[witgen(world = "...")]`, which
+    /// exports unless `import` is given as well. Only functions, traits, impls and modules can be
+    /// imported or exported, an impl standing for the interface which declares its resource.
+    pub fn world(&self) -> Result<Option<(Option<String>, bool)>> {
+        let attrs = WitgenAttrs::from_attrs(self.attrs().unwrap_or_default())?;
+        if !attrs.import && !attrs.export && attrs.world.is_none() {
+            return Ok(None);
+        }
+        if attrs.import && attrs.export {
+            bail!("an item can't be both imported and exported");
+        }
+        match self {
+            Wit::Function(_) | Wit::Interface(_) | Wit::Resource(_) | Wit::Mod(..) => {
+                Ok(Some((attrs.world, !attrs.import)))
+            }
+            _ => bail!("only functions, traits, impls and modules can be imported or exported"),
+        }
+    }
+
+    /// Whether this is a generic struct, enum or type alias
+    pub fn is_template(&self) -> bool {
+        let generics = match self {
+            Wit::Record(item) => &item.generics,
+            Wit::Variant(item) => &item.generics,
+            Wit::Type(item) => &item.generics,
+            _ => return false,
+        };
+        !type_params(generics).is_empty()
+    }
+
+    /// Instantiation and name of a type alias annotated with `#[witgen(name = "...")]`
+    fn name_override(&self) -> Result<Option<(&SynType, String)>> {
+        match self {
+            Wit::Type(item) => Ok(WitgenAttrs::from_attrs(&item.attrs)?
+                .name
+                .map(|name| (item.ty.as_ref(), name))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Run a `syn` visitor over the items of this tree
+    pub(crate) fn visit_syn<'ast>(&'ast self, visitor: &mut impl Visit<'ast>) {
+        match self {
+            Wit::Mod(wit, _, _) => wit.iter().for_each(|item| item.visit_syn(visitor)),
+            Wit::Record(item) => visitor.visit_item_struct(item),
+            Wit::Function(item) => visitor.visit_item_fn(item),
+            Wit::Variant(item) => visitor.visit_item_enum(item),
+            Wit::Type(item) => visitor.visit_item_type(item),
+            Wit::Use(item) => visitor.visit_item_use(item),
+            Wit::Interface(item) => visitor.visit_item_trait(item),
+            Wit::Resource(item) => visitor.visit_item_impl(item),
+            Wit::Flags(item) => visitor.visit_item_macro(item),
+        }
+    }
+
+    /// Run a `syn` visitor over the types the definitions of this tree are generated from: the
+    /// parameters and results of functions and exported methods, the fields of structs and enum
+    /// variants and the targets of type aliases
+    pub(crate) fn visit_types<'ast>(&'ast self, visitor: &mut impl Visit<'ast>) {
+        match self {
+            Wit::Mod(wit, _, _) => wit.iter().for_each(|item| item.visit_types(visitor)),
+            Wit::Record(item) => visitor.visit_fields(&item.fields),
+            Wit::Function(item) => visit_signature_types(&item.sig, visitor),
+            Wit::Variant(item) => item
+                .variants
+                .iter()
+                .for_each(|variant| visitor.visit_fields(&variant.fields)),
+            Wit::Type(item) => visitor.visit_type(&item.ty),
+            Wit::Interface(item) => {
+                for item in &item.items {
+                    if let TraitItem::Method(method) = item {
+                        visit_signature_types(&method.sig, visitor);
+                    }
+                }
+            }
+            Wit::Resource(item) => item
+                .items
+                .iter()
+                .filter_map(pub_method)
+                .for_each(|method| visit_signature_types(&method.sig, visitor)),
+            Wit::Use(_) | Wit::Flags(_) => {}
+        }
+    }
+
+    /// Name of the Rust item
+    pub fn name(&self) -> String {
+        match self {
+            Wit::Mod(_, _, ident) => ident
+                .as_ref()
+                .map_or_else(|| "crate".to_string(), ToString::to_string),
+            Wit::Record(item) => item.ident.to_string(),
+            Wit::Function(item) => item.sig.ident.to_string(),
+            Wit::Variant(item) => item.ident.to_string(),
+            Wit::Type(item) => item.ident.to_string(),
+            Wit::Use(item) => item.tree.to_token_stream().to_string(),
+            Wit::Interface(item) => item.ident.to_string(),
+            Wit::Resource(item) => item.self_ty.to_token_stream().to_string(),
+            Wit::Flags(item) => format!("{}!", item.mac.path.to_token_stream()),
+        }
+    }
+
+    /// Diagnostic pointing at the Rust item which generated the definition `err` is about
+    ///
+    /// `output` is the legacy wit generated from this tree and `err` the error `wit_parser`
+    /// failed to parse it with, located at `<file>:<line>:<column>`. The item is the one
+    /// declaring the closest top-level definition up to that line, if it is one of this tree.
+    pub fn diagnose_output(&self, output: &str, err: &anyhow::Error) -> Option<Diagnostic> {
+        let (line, _) = error_location(err)?;
+        let lines: Vec<&str> = output.lines().take(line).collect();
+        let name = lines.into_iter().rev().find_map(declared_name)?;
+        let (item, module_path) = self.find_declaring(&name, &mut vec![])?;
+        Some(Diagnostic::new(item.name(), module_path, err, item.span()))
+    }
+
+    /// Call `f` on every leaf item of this tree along with its module path
+    pub(crate) fn for_each_leaf<'a>(
+        &'a self,
+        module_path: &mut Vec<String>,
+        f: &mut dyn FnMut(&'a Wit, &[String]),
+    ) {
+        match self {
+            Wit::Mod(wit, _, ident) => {
+                if let Some(ident) = ident {
+                    module_path.push(ident.to_string());
+                }
+                wit.iter()
+                    .for_each(|item| item.for_each_leaf(module_path, f));
+                if ident.is_some() {
+                    module_path.pop();
+                }
+            }
+            item => f(item, module_path),
+        }
+    }
+
+    /// Name of each definition generated by this tree along with the module path of the Rust
+    /// item generating it
+    ///
+    /// Instantiations of generic types aren't listed since any module can use them.
+    pub fn declaring_modules(&self) -> Vec<(String, Vec<String>)> {
+        let mut res = vec![];
+        self.collect_declaring_modules(&mut vec![], &mut res);
+        res
+    }
+
+    /// Modules generating interfaces are listed as a whole, under the name of the interface
+    fn collect_declaring_modules(
+        &self,
+        module_path: &mut Vec<String>,
+        res: &mut Vec<(String, Vec<String>)>,
+    ) {
+        match self {
+            Wit::Mod(wit, _, ident) => {
+                if let Some(ident) = ident {
+                    module_path.push(ident.to_string());
+                }
+                match self.module_interface() {
+                    Ok(Some(name)) => res.push((name, module_path.clone())),
+                    _ => wit
+                        .iter()
+                        .for_each(|item| item.collect_declaring_modules(module_path, res)),
+                }
+                if ident.is_some() {
+                    module_path.pop();
+                }
+            }
+            item => {
+                for wit_item in item.to_items().unwrap_or_default() {
+                    if let Some(name) = wit_item.name() {
+                        res.push((name.to_string(), module_path.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Leaf item generating the definition named `name`, along with its module path
+    fn find_declaring(
+        &self,
+        name: &str,
+        module_path: &mut Vec<String>,
+    ) -> Option<(&Wit, Vec<String>)> {
+        match self {
+            Wit::Mod(wit, _, ident) => {
+                if self.module_interface().ok()?.as_deref() == Some(name) {
+                    return Some((self, module_path.clone()));
+                }
+                if let Some(ident) = ident {
+                    module_path.push(ident.to_string());
+                }
+                let res = wit
+                    .iter()
+                    .find_map(|item| item.find_declaring(name, module_path));
+                if ident.is_some() {
+                    module_path.pop();
+                }
+                res
+            }
+            item => {
+                let items = item.to_items().ok()?;
+                if items.iter().any(|wit_item| wit_item.name() == Some(name)) {
+                    Some((item, module_path.clone()))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Span of the Rust item's name
+    pub fn span(&self) -> Span {
+        match self {
+            Wit::Mod(_, _, ident) => ident.as_ref().map_or_else(Span::call_site, Ident::span),
+            Wit::Record(item) => item.ident.span(),
+            Wit::Function(item) => item.sig.ident.span(),
+            Wit::Variant(item) => item.ident.span(),
+            Wit::Type(item) => item.ident.span(),
+            Wit::Use(item) => item.tree.span(),
+            Wit::Interface(item) => item.ident.span(),
+            Wit::Resource(item) => item.self_ty.span(),
+            Wit::Flags(item) => item.mac.path.span(),
+        }
+    }
+
+    /// Convert the whole tree into a document
+    ///
+    /// Items which can't be represented are left out of the document and reported as diagnostics.
+    pub fn to_document(&self) -> (Document, Diagnostics) {
+        let mut doc = Document::default();
+        let mut diagnostics = Diagnostics::default();
+        let mut generics = Monomorphizer::default();
+        self.collect(&mut vec![], &mut doc, &mut diagnostics, &mut generics);
+        generics.instantiate(&mut doc, &mut diagnostics);
+        // References use the kebab-cased Rust name, point them to the renamed types
+        for (from, to) in self.renames() {
+            doc.rename(&from, &to);
+        }
+        doc.resolve_handles();
+        self.check_collisions(&mut doc, &mut diagnostics);
+        (doc, diagnostics)
+    }
+
+    /// Report the definitions which have the same name once kebab-cased, e.g. `HttpConfig` and
+    /// `HTTPConfig`, or a struct `FooBar` and the record of the variant `Foo::Bar`, leaving out
+    /// all of them but the first
+    ///
+    /// Types, functions and interfaces have names of their own, which are unique per
+    /// interface. Items declaring a name twice, e.g. a field, are left out
+    /// as well, see [`WitItem::duplicate_name`].
+    fn check_collisions(&self, doc: &mut Document, diagnostics: &mut Diagnostics) {
+        let mut origins = vec![];
+        self.collect_origins(&mut vec![], &mut vec![], &mut origins);
+        let root = Origin {
+            scope: vec![],
+            namespace: Namespace::Interface,
+            name: String::new(),
+            item: self,
+            module_path: vec![],
+        };
+        check_scope(&mut doc.items, &mut vec![], &origins, &root, diagnostics);
+    }
+
+    fn collect_origins<'a>(
+        &'a self,
+        scope: &mut Vec<String>,
+        module_path: &mut Vec<String>,
+        res: &mut Vec<Origin<'a>>,
+    ) {
+        match self {
+            Wit::Mod(wit, _, ident) => {
+                let interface = self.module_interface().ok().flatten();
+                if let Some(name) = &interface {
+                    res.push(Origin {
+                        scope: scope.clone(),
+                        namespace: Namespace::Interface,
+                        name: name.clone(),
+                        item: self,
+                        module_path: module_path.clone(),
+                    });
+                    scope.push(name.clone());
+                }
+                if let Some(ident) = ident {
+                    module_path.push(ident.to_string());
+                }
+                for item in wit {
+                    item.collect_origins(scope, module_path, res);
+                }
+                if ident.is_some() {
+                    module_path.pop();
+                }
+                if interface.is_some() {
+                    scope.pop();
+                }
+            }
+            item => {
+                for wit_item in item.to_items().unwrap_or_default() {
+                    if let Some(name) = wit_item.name() {
+                        res.push(Origin {
+                            scope: scope.clone(),
+                            namespace: Namespace::of(&wit_item),
+                            name: name.to_string(),
+                            item,
+                            module_path: module_path.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Kebab-cased Rust name and wit name of the types renamed with `#[witgen(rename = "...")]`
+    pub fn renames(&self) -> Vec<(String, String)> {
+        self.flatten()
+            .into_iter()
+            .filter_map(Wit::renamed)
+            .collect()
+    }
+
+    fn renamed(&self) -> Option<(String, String)> {
+        let ident = match self {
+            Wit::Record(item) => &item.ident,
+            Wit::Variant(item) => &item.ident,
+            Wit::Type(item) => &item.ident,
+            Wit::Resource(item) => match item.self_ty.as_ref() {
+                SynType::Path(path) => path.path.get_ident()?,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let rename = WitgenAttrs::from_attrs(self.attrs()?).ok()?.rename?;
+        Some((ident.to_string().to_kebab_case(), rename))
+    }
+
+    fn collect<'a>(
+        &'a self,
+        module_path: &mut Vec<String>,
+        doc: &mut Document,
+        diagnostics: &mut Diagnostics,
+        generics: &mut Monomorphizer<'a>,
+    ) {
+        match self {
+            Wit::Mod(wit, attrs, ident) => {
+                let interface = self.module_interface().and_then(|name| {
+                    let docs = get_doc_comment(attrs, false)?;
+                    let world = self.world()?;
+                    Ok(name.map(|name| (name, docs, world)))
+                });
+                let interface = match interface {
+                    Ok(interface) => interface,
+                    Err(err) => {
+                        diagnostics.push(Diagnostic::new(
+                            self.name(),
+                            module_path.clone(),
+                            &err,
+                            self.span(),
+                        ));
+                        return;
+                    }
+                };
+                if let Some(ident) = ident {
+                    module_path.push(ident.to_string());
+                }
+                match interface {
+                    Some((name, docs, world)) => {
+                        // Items know the interface they are declared in from the name
+                        let mut items = Document {
+                            name: Some(name.clone()),
+                            ..Document::default()
+                        };
+                        for item in wit {
+                            item.collect(module_path, &mut items, diagnostics, generics);
+                        }
+                        if let Some((world, export)) = world {
+                            doc.add_to_world(
+                                world,
+                                export,
+                                WorldItem::Interface(Some(name.clone())),
+                            );
+                        }
+                        for world in items.worlds {
+                            for item in world.imports {
+                                doc.add_to_world(world.name.clone(), false, item);
+                            }
+                            for item in world.exports {
+                                doc.add_to_world(world.name.clone(), true, item);
+                            }
+                        }
+                        doc.items.push(WitItem::Interface(Interface {
+                            name,
+                            docs,
+                            items: items.items,
+                        }));
+                    }
+                    None => {
+                        for item in wit {
+                            item.collect(module_path, doc, diagnostics, generics);
+                        }
+                    }
+                }
+                if ident.is_some() {
+                    module_path.pop();
+                }
+            }
+            item => {
+                let res = item.to_items().and_then(|items| {
+                    if item.is_template() {
+                        generics.add_template(module_path, item);
+                    } else {
+                        generics.visit(item);
+                    }
+                    if let Some((ty, name)) = item.name_override()? {
+                        generics.add_name(ty, name)?;
+                    }
+                    Ok((items, item.world()?))
+                });
+                match res {
+                    Ok((items, world)) => {
+                        if let Some((world, export)) = world {
+                            let world_item = match (item, items.first()) {
+                                (Wit::Function(_), Some(WitItem::Function(func))) => {
+                                    WorldItem::Function {
+                                        interface: doc.name.clone(),
+                                        name: func.name.clone(),
+                                    }
+                                }
+                                (Wit::Interface(_), Some(WitItem::Interface(interface))) => {
+                                    WorldItem::Interface(Some(interface.name.clone()))
+                                }
+                                // Resources are types of the enclosing interface
+                                _ => WorldItem::Interface(doc.name.clone()),
+                            };
+                            doc.add_to_world(world, export, world_item);
+                        }
+                        doc.items.extend(items)
+                    }
+                    Err(err) => diagnostics.push(Diagnostic::new(
+                        item.name(),
+                        module_path.clone(),
+                        &err,
+                        item.span(),
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Generate the wit definitions of this item in the given dialect
+    ///
+    /// Items which can't be represented are left out, see [`Wit::to_document`] to get them reported.
+    pub fn to_wit_string(&self, dialect: Dialect) -> String {
+        Printer::new(dialect)
+            .print_items(&self.to_document().0.items, 0)
+            .unwrap_or_default()
     }
 
     pub fn validate(self) -> Result<Self> {
         use Wit::*;
         match self {
-            Mod(_, _) => Ok(self),
+            Mod(_, _, _) => Ok(self),
             other if has_witgen_macro(&self.attrs()) => Ok(other),
+            // `#[witgen]` can also annotate the structs declared in the macro
+            Flags(item)
+                if parse_bitflags(&item).map_or(false, |structs| {
+                    structs
+                        .iter()
+                        .any(|strukt| has_witgen_macro(&Some(&strukt.attrs)))
+                }) =>
+            {
+                Ok(Flags(item))
+            }
             _ => bail!("Has no witgen macro"),
         }
     }
 }
 
+/// Rust item generating a definition, within the interfaces named by `scope`
+struct Origin<'a> {
+    scope: Vec<String>,
+    namespace: Namespace,
+    name: String,
+    item: &'a Wit,
+    module_path: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+    Type,
+    Function,
+    Interface,
+}
+
+impl Namespace {
+    fn of(item: &WitItem) -> Self {
+        match item {
+            WitItem::Function(_) => Namespace::Function,
+            WitItem::Interface(_) => Namespace::Interface,
+            _ => Namespace::Type,
+        }
+    }
+}
+
+impl Origin<'_> {
+    fn diagnostic(&self, message: String) -> Diagnostic {
+        Diagnostic::new(
+            self.item.name(),
+            self.module_path.clone(),
+            &anyhow::anyhow!(message),
+            self.item.span(),
+        )
+    }
+}
+
+/// See [`Wit::check_collisions`], `parent` generates the interface `items` are declared in
+fn check_scope(
+    items: &mut Vec<WitItem>,
+    scope: &mut Vec<String>,
+    origins: &[Origin],
+    parent: &Origin,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut seen: Vec<(Namespace, String)> = vec![];
+    let mut res = vec![];
+    for mut item in std::mem::take(items) {
+        let namespace = Namespace::of(&item);
+        let name = item.name().map(String::from);
+        let declaring: Vec<&Origin> = origins
+            .iter()
+            .filter(|origin| {
+                origin.scope == *scope
+                    && origin.namespace == namespace
+                    && Some(&origin.name) == name.as_ref()
+            })
+            .collect();
+        let occurrence = seen
+            .iter()
+            .filter(|(ns, seen)| *ns == namespace && Some(seen) == name.as_ref())
+            .count();
+        let origin = declaring
+            .get(occurrence)
+            .or_else(|| declaring.last())
+            .copied()
+            .unwrap_or(parent);
+
+        if let Some(name) = &name {
+            if occurrence > 0 {
+                let first = declaring
+                    .first()
+                    .filter(|first| !std::ptr::eq(first.item, origin.item));
+                let mut diagnostic = match first {
+                    Some(first) => origin.diagnostic(format!(
+                        "`{name}` is already generated by `{}`",
+                        first.item.name()
+                    )),
+                    None => origin.diagnostic(format!("`{name}` is declared twice")),
+                };
+                diagnostic.help = first.map(|first| {
+                    Box::new(first.diagnostic(
+                        "rename one of them with `#[witgen(rename = \"...\")]`".to_string(),
+                    ))
+                });
+                diagnostics.push(diagnostic);
+                continue;
+            }
+        }
+        if let Some((kind, duplicate)) = item.duplicate_name() {
+            diagnostics.push(origin.diagnostic(duplicate_message(origin.item, kind, duplicate)));
+            continue;
+        }
+        if let WitItem::Interface(interface) = &mut item {
+            scope.push(interface.name.clone());
+            check_scope(&mut interface.items, scope, origins, origin, diagnostics);
+            scope.pop();
+        }
+        seen.extend(name.map(|name| (namespace, name)));
+        res.push(item);
+    }
+    *items = res;
+}
+
+/// Run a `syn` visitor over the parameter and result types of `sig`
+fn visit_signature_types<'ast>(sig: &'ast Signature, visitor: &mut impl Visit<'ast>) {
+    for input in &sig.inputs {
+        if let FnArg::Typed(arg) = input {
+            visitor.visit_type(&arg.ty);
+        }
+    }
+    if let ReturnType::Type(_, ty) = &sig.output {
+        visitor.visit_type(ty);
+    }
+}
+
+/// Error about the `kind` named `name` twice in `item`, naming the Rust identifiers it comes
+/// from when they can be found
+fn duplicate_message(item: &Wit, kind: &str, name: &str) -> String {
+    let mut visitor = IdentVisitor {
+        name,
+        found: vec![],
+    };
+    item.visit_syn(&mut visitor);
+    match visitor.found.as_slice() {
+        [first, second, ..] => {
+            format!("{kind}s `{first}` and `{second}` are both named `{name}` in wit")
+        }
+        _ => format!("{kind} `{name}` is declared twice"),
+    }
+}
+
+/// Finds the identifiers which are kebab-cased into `name`
+struct IdentVisitor<'a> {
+    name: &'a str,
+    found: Vec<String>,
+}
+
+impl<'ast> Visit<'ast> for IdentVisitor<'_> {
+    fn visit_ident(&mut self, ident: &'ast Ident) {
+        let ident = ident.unraw().to_string();
+        if ident.to_kebab_case() == self.name && !self.found.contains(&ident) {
+            self.found.push(ident);
+        }
+    }
+}
+
+/// Name declared by an unindented line of legacy wit, e.g. `foo` for `record foo {`
+pub(crate) fn declared_name(line: &str) -> Option<String> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut words = line.split_whitespace();
+    let name = match (words.next()?, words.next()?) {
+        ("record" | "variant" | "enum" | "flags" | "resource" | "interface" | "type", name) => {
+            name.trim_end_matches(['{', '='])
+        }
+        (name, func) if name.ends_with(':') && func.starts_with("func") => {
+            name.trim_end_matches(':')
+        }
+        _ => return None,
+    };
+    Some(name.trim_start_matches('%').to_string())
+}
+
 fn has_witgen_macro(attrs: &Option<&[Attribute]>) -> bool {
     attrs.map_or(false, |attrs| {
         for attr in attrs.iter() {
-            if is_witgen_macro(attr) {
+            if is_witgen_attr(attr) {
                 return true;
             }
         }
@@ -73,22 +797,16 @@ fn has_witgen_macro(attrs: &Option<&[Attribute]>) -> bool {
     })
 }
 
-fn is_witgen_macro(attr: &Attribute) -> bool {
-    // TODO: make this not use string comparison.
-    format!("{:#?}", attr.path).contains("witgen")
-}
-
 impl From<File> for Wit {
     fn from(file: File) -> Self {
-        Wit::Mod(Wit::from_items(file.items), vec![])
+        Wit::Mod(Wit::from_items(file.items), vec![], None)
     }
 }
 
-impl TryFrom<Item> for Wit {
-    type Error = anyhow::Error;
-
-    fn try_from(item: Item) -> Result<Self, Self::Error> {
-        match item {
+impl Wit {
+    /// Item regardless of whether it is annotated with `#[witgen]`
+    fn from_item(item: Item) -> Result<Self> {
+        let wit = match item {
             Item::Enum(item) => Wit::Variant(item),
             Item::Fn(item) => Wit::Function(item),
             Item::Struct(item) => Wit::Record(item),
@@ -96,14 +814,58 @@ impl TryFrom<Item> for Wit {
             Item::Use(item) => Wit::Use(item),
             Item::Trait(item) => Wit::Interface(item),
             Item::Impl(item) => Wit::Resource(item),
+            Item::Macro(item) if is_bitflags(&item) => Wit::Flags(item),
             Item::Mod(ItemMod {
                 content: Some((_, items)),
                 attrs,
+                ident,
                 ..
-            }) => Wit::Mod(Wit::from_items(items), attrs),
-            _ => bail!("cannot prase item"),
+            }) => Wit::Mod(Wit::from_items(items), attrs, Some(ident)),
+            other => {
+                return Err(spanned_error(
+                    other,
+                    "Cannot put witgen proc macro on this kind of item",
+                ))
+            }
+        };
+        Ok(wit)
+    }
+
+    /// Check that an item can be converted to wit, used by the `#[witgen]` proc macro to
+    /// report unsupported items at compile time
+    ///
+    /// `args` are the arguments the macro was given, e.g. `rename = "foo"`.
+    pub fn check_item(args: proc_macro2::TokenStream, item: Item) -> syn::Result<()> {
+        // The generators read the arguments from the item's attributes
+        let item = parse::<Item>(quote! {#[witgen(#args)] #item})?;
+        let wit = Wit::from_item(item).map_err(|err| to_syn_error(err, Span::call_site()))?;
+        // The items of a module carry their own `#[witgen]`, which reports their errors
+        if let Wit::Mod(nested, _, _) = &wit {
+            if nested.iter().any(|item| item.to_items().is_err()) {
+                return Ok(());
+            }
         }
-        .validate()
+        let items = wit
+            .to_items()
+            .and_then(|items| wit.world().map(|_| items))
+            .map_err(|err| to_syn_error(err, wit.span()))?;
+        for item in &items {
+            if let Some((kind, name)) = item.duplicate_name() {
+                return Err(syn::Error::new(
+                    wit.span(),
+                    duplicate_message(&wit, kind, name),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<Item> for Wit {
+    type Error = anyhow::Error;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Wit::from_item(item)?.validate()
     }
 }
 
@@ -143,159 +905,190 @@ impl TryFrom<&str> for Wit {
 
 impl Display for Wit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let doc = self.get_doc().unwrap_or_default();
-        let wit_str = match self {
-            Wit::Mod(wit, _) => Ok(wit
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<String>>()
-                .join("\n")),
-            Wit::Record(item) => gen_wit_struct(item),
-            Wit::Function(item) => gen_wit_function(item),
-            Wit::Variant(item) => gen_wit_enum(item),
-            Wit::Type(item) => gen_wit_type_alias(item),
-            Wit::Use(item) => gen_wit_import(item),
-            Wit::Resource(item) => gen_wit_impl(item),
-            Wit::Interface(item) => gen_wit_trait(item),
-        }
-        .unwrap_or_default();
-        write!(f, "{doc}{wit_str}")
+        write!(f, "{}", self.to_wit_string(Dialect::Legacy))
     }
 }
 
+/// Rust types converted to wit builtin types, or to the type they wrap
+pub(crate) const BUILTIN_TYPES: &[&str] = &[
+    "Vec", "Option", "HashMap", "Result", "Box", "Rc", "Arc", "Cow", "String", "str", "bool",
+    "char", "u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize", "f32", "f64",
+    "Self",
+];
+
 pub(crate) trait ToWitType {
-    fn to_wit(&self) -> Result<String>;
+    fn to_wit(&self) -> Result<WitType>;
 }
 
 impl ToWitType for SynType {
-    fn to_wit(&self) -> Result<String> {
-        let res = match self {
-            SynType::Array(array) => {
-                format!("list<{}>", array.elem.to_wit()?)
-            }
-            SynType::Slice(array) => {
-                format!("list<{}>", array.elem.to_wit()?)
+    fn to_wit(&self) -> Result<WitType> {
+        // Errors point at the innermost type which couldn't be converted
+        syn_type_to_wit(self).map_err(|err| {
+            if err.is::<syn::Error>() {
+                err
+            } else {
+                spanned_error(self, err)
             }
-            SynType::Path(path) => {
-                let last_path_seg = path.path.segments.last().ok_or_else(|| {
-                    anyhow::anyhow!(
-                        "cannot get type path segment for type '{}'",
-                        self.to_token_stream()
-                    )
-                })?;
-                let global_ty = last_path_seg.ident.to_string();
-                match global_ty.as_str() {
-                    // Add Box/ARC/RC ?
-                    wrapper_ty @ ("Vec" | "Option") => match &last_path_seg.arguments {
-                        syn::PathArguments::AngleBracketed(generic_args) => {
-                            if generic_args.args.len() > 1 {
-                                bail!("generic args of {} should not be more than 1", wrapper_ty);
-                            }
-                            match generic_args.args.first().unwrap() {
-                                syn::GenericArgument::Type(ty) => {
-                                    let new_ty_name = match wrapper_ty {
-                                        "Vec" => "list",
-                                        "Option" => "option",
-                                        _ => unreachable!(),
-                                    };
-                                    format!("{}<{}>", new_ty_name, ty.to_wit()?)
-                                }
-                                other => {
-                                    bail!("generic args type {:?} is not implemented", other)
+        })
+    }
+}
+
+fn syn_type_to_wit(syn_ty: &SynType) -> Result<WitType> {
+    let res = match syn_ty {
+        SynType::Array(array) => WitType::List(Box::new(array.elem.to_wit()?)),
+        SynType::Slice(array) => WitType::List(Box::new(array.elem.to_wit()?)),
+        SynType::Path(path) => {
+            let last_path_seg = path.path.segments.last().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "cannot get type path segment for type '{}'",
+                    syn_ty.to_token_stream()
+                )
+            })?;
+            let global_ty = last_path_seg.ident.to_string();
+            match global_ty.as_str() {
+                wrapper_ty @ ("Vec" | "Option") => match &last_path_seg.arguments {
+                    syn::PathArguments::AngleBracketed(generic_args) => {
+                        if generic_args.args.len() > 1 {
+                            bail!("generic args of {} should not be more than 1", wrapper_ty);
+                        }
+                        match generic_args.args.first().unwrap() {
+                            syn::GenericArgument::Type(ty) => {
+                                let ty = Box::new(ty.to_wit()?);
+                                match wrapper_ty {
+                                    "Vec" => WitType::List(ty),
+                                    "Option" => WitType::Option(ty),
+                                    _ => unreachable!(),
                                 }
                             }
-                        }
-                        syn::PathArguments::Parenthesized(_) | syn::PathArguments::None => {
-                            bail!("parenthized path argument is not implemented")
-                        }
-                    },
-                    wrapper_ty @ "HashMap" => match &last_path_seg.arguments {
-                        syn::PathArguments::AngleBracketed(generic_args) => {
-                            if generic_args.args.len() != 2 {
-                                bail!("generic args of {} should be 2", wrapper_ty);
+                            other => {
+                                bail!("generic args type {:?} is not implemented", other)
                             }
-
-                            let args = generic_args
-                                .args
-                                .iter()
-                                .map(|arg| match arg {
-                                    syn::GenericArgument::Type(ty) => ty.to_wit(),
-                                    other => {
-                                        bail!("generic args type {:?} is not implemented", other)
-                                    }
-                                })
-                                .collect::<Result<Vec<String>>>()?;
-                            format!("list<tuple<{}>>", args.join(","))
                         }
-                        syn::PathArguments::Parenthesized(_) | syn::PathArguments::None => {
-                            bail!("parenthized path argument is not implemented")
+                    }
+                    syn::PathArguments::Parenthesized(_) | syn::PathArguments::None => {
+                        bail!("parenthized path argument is not implemented")
+                    }
+                },
+                wrapper_ty @ "HashMap" => match &last_path_seg.arguments {
+                    syn::PathArguments::AngleBracketed(generic_args) => {
+                        if generic_args.args.len() != 2 {
+                            bail!("generic args of {} should be 2", wrapper_ty);
                         }
-                    },
-                    wrapper_ty @ "Result" => match &last_path_seg.arguments {
-                        syn::PathArguments::AngleBracketed(generic_args) => {
-                            if generic_args.args.len() > 2 {
-                                bail!("generic args of {} should not be more than 2", wrapper_ty);
-                            }
-                            let generic_args = generic_args
-                                .args
-                                .iter()
-                                .map(|t| match t {
-                                    syn::GenericArgument::Type(ty) => ty.to_wit(),
-                                    other => Err(anyhow::anyhow!(
-                                        "generic args type {:?} is not implemented",
-                                        other
-                                    )),
-                                })
-                                .collect::<Result<Vec<String>>>()?;
-
-                            format!("expected<{}>", generic_args.join(", "))
+
+                        let args = generic_args
+                            .args
+                            .iter()
+                            .map(|arg| match arg {
+                                syn::GenericArgument::Type(ty) => ty.to_wit(),
+                                other => {
+                                    bail!("generic args type {:?} is not implemented", other)
+                                }
+                            })
+                            .collect::<Result<Vec<WitType>>>()?;
+                        WitType::List(Box::new(WitType::Tuple(args)))
+                    }
+                    syn::PathArguments::Parenthesized(_) | syn::PathArguments::None => {
+                        bail!("parenthized path argument is not implemented")
+                    }
+                },
+                wrapper_ty @ "Result" => match &last_path_seg.arguments {
+                    syn::PathArguments::AngleBracketed(generic_args) => {
+                        if generic_args.args.len() > 2 {
+                            bail!("generic args of {} should not be more than 2", wrapper_ty);
                         }
-                        syn::PathArguments::Parenthesized(_) | syn::PathArguments::None => {
-                            bail!("parenthized path argument is not implemented")
+                        let mut generic_args = generic_args
+                            .args
+                            .iter()
+                            .map(|t| match t {
+                                syn::GenericArgument::Type(ty) => ty.to_wit(),
+                                other => Err(anyhow::anyhow!(
+                                    "generic args type {:?} is not implemented",
+                                    other
+                                )),
+                            })
+                            .collect::<Result<Vec<WitType>>>()?
+                            .into_iter();
+                        let ok = generic_args.next().ok_or_else(|| {
+                            anyhow::anyhow!("generic args of {} are missing", wrapper_ty)
+                        })?;
+                        WitType::Result {
+                            ok: Box::new(ok),
+                            err: generic_args.next().map(Box::new),
                         }
-                    },
-                    "String" => "string".to_string(),
-                    _ => {
-                        let ident = path.path.get_ident().ok_or_else(|| {
-                          anyhow::anyhow!("cannot get identifier for a type '{}', type who takes generics are not currently supported", self.to_token_stream())
-                      })?;
-                        match ident.to_string().as_str() {
-                            ident @ ("i8" | "i16" | "i32" | "i64") => {
-                                format!("s{}", ident.trim_start_matches('i'))
-                            }
-                            "usize" => String::from("u64"),
-                            "isize" => String::from("i64"),
-                            ident @ ("u8" | "u16" | "u32" | "u64" | "char" | "bool") => {
-                                ident.to_string()
-                            }
-                            "f32" => "float32".to_string(),
-                            "f64" => "float64".to_string(),
-                            ident => wit_ident(ident)?,
+                    }
+                    syn::PathArguments::Parenthesized(_) | syn::PathArguments::None => {
+                        bail!("parenthized path argument is not implemented")
+                    }
+                },
+                // Smart pointers are represented by the type they point to
+                wrapper_ty @ ("Box" | "Rc" | "Arc" | "Cow") => match &last_path_seg.arguments {
+                    syn::PathArguments::AngleBracketed(generic_args) => {
+                        // Skip the lifetime of `Cow<'a, T>`
+                        let mut types = generic_args.args.iter().filter_map(|arg| match arg {
+                            syn::GenericArgument::Type(ty) => Some(ty),
+                            _ => None,
+                        });
+                        match (types.next(), types.next()) {
+                            (Some(ty), None) => return ty.to_wit(),
+                            _ => bail!("generic args of {} should be a single type", wrapper_ty),
                         }
                     }
+                    syn::PathArguments::Parenthesized(_) | syn::PathArguments::None => {
+                        bail!("parenthized path argument is not implemented")
+                    }
+                },
+                "String" => WitType::String,
+                // Instantiation of a generic type, see `crate::generics`
+                ident if type_args(path).map_or(false, |args| !args.is_empty()) => {
+                    let args = type_args(path)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(ToWitType::to_wit)
+                        .collect::<Result<Vec<WitType>>>()?;
+                    WitType::Named(instance_name(&wit_ident(ident), &args))
+                }
+                _ => {
+                    let ident = path.path.get_ident().ok_or_else(|| {
+                      anyhow::anyhow!("cannot get identifier for a type '{}', type who takes generics are not currently supported", syn_ty.to_token_stream())
+                  })?;
+                    match ident.to_string().as_str() {
+                        "i8" => WitType::S8,
+                        "i16" => WitType::S16,
+                        "i32" => WitType::S32,
+                        "i64" | "isize" => WitType::S64,
+                        "u8" => WitType::U8,
+                        "u16" => WitType::U16,
+                        "u32" => WitType::U32,
+                        "u64" | "usize" => WitType::U64,
+                        "f32" => WitType::F32,
+                        "f64" => WitType::F64,
+                        "char" => WitType::Char,
+                        "bool" => WitType::Bool,
+                        // Only reachable behind a reference or a pointer, e.g. `&str` or `Box<str>`
+                        "str" => WitType::String,
+                        // A keyword is only an error where the type is declared, since it can
+                        // be renamed there with `#[witgen(rename = "...")]`
+                        ident => WitType::Named(ident.to_kebab_case()),
+                    }
                 }
             }
-            SynType::Tuple(tuple) => {
-                format!(
-                    "tuple<{}>",
-                    tuple
-                        .elems
-                        .iter()
-                        .map(|ty| ty.to_wit())
-                        .collect::<Result<Vec<String>>>()?
-                        .join(", ")
-                )
-            }
-            SynType::Reference(r) => {
-                let TypeReference { elem, .. } = r;
-                return elem.to_wit();
-            }
-            _ => bail!(
-                "cannot serialize this type '{}' to wit",
-                self.to_token_stream()
-            ),
-        };
+        }
+        SynType::Tuple(tuple) => WitType::Tuple(
+            tuple
+                .elems
+                .iter()
+                .map(|ty| ty.to_wit())
+                .collect::<Result<Vec<WitType>>>()?,
+        ),
+        SynType::Reference(r) => {
+            let TypeReference { elem, .. } = r;
+            return elem.to_wit();
+        }
+        _ => bail!(
+            "cannot serialize this type '{}' to wit",
+            syn_ty.to_token_stream()
+        ),
+    };
 
-        Ok(res)
-    }
+    Ok(res)
 }
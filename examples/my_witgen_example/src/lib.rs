@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use witgen::witgen;
 
 #[witgen]
-use example_dep::*;
+use example_dep::{ExternalDep, OtherColors as Palette};
 
 mod extra_type;
 use extra_type::*;
@@ -108,6 +108,11 @@ fn test_tuple(other: Vec<u8>, test_struct: TestStruct, other_enum: TestEnum) ->
     (String::from("test"), 0i64)
 }
 
+#[witgen]
+fn test_borrowed(name: &str, bytes: &[u8], shared: std::sync::Arc<str>) -> Box<str> {
+    name.into()
+}
+
 #[witgen]
 struct HasHashMap {
     map: HashMap<String, TestStruct>,
@@ -120,6 +125,46 @@ fn use_string_alias(s: StringAlias) -> StringAlias {
 
 fn has_no_macro() {}
 
+/// Renamed in wit
+#[witgen(rename = "renamed-struct")]
+struct OriginalName {
+    /// `type` is a wit keyword
+    #[witgen(rename = "kind")]
+    r#type: String,
+}
+
+/// Struct of `bool`s generated as flags
+#[witgen(flags)]
+struct Permissions {
+    /// Can be read
+    read: bool,
+    write: bool,
+}
+
+/// Keywords are escaped
+#[witgen]
+struct Keywords {
+    r#type: String,
+    from: String,
+}
+
+#[witgen(rename = "use-renamed")]
+fn use_original_name(#[witgen(rename = "value")] original: OriginalName) -> OriginalName {
+    original
+}
+
+/// A page of results
+#[witgen]
+struct Page<T> {
+    items: Vec<T>,
+    total: u32,
+}
+
+#[witgen]
+fn paginate(colors: Page<Colors>) -> Page<Page<String>> {
+    todo!()
+}
+
 #[witgen]
 type Float32Bit = f32;
 
@@ -150,7 +195,7 @@ impl ExampleResource {
         todo!()
     }
 
-    pub fn color() -> OtherColors {
+    pub fn color() -> Palette {
         todo!()
     }
 }
@@ -1,11 +1,18 @@
-use std::path::PathBuf;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::Result;
-use cargo_witgen::Witgen;
+use cargo_witgen::{Split, Witgen};
 use k9::assert_matches_snapshot;
 use wit_parser::Interface;
-use witgen_macro_helper::{DefaultResolver, Resolver, Wit};
+use witgen_macro_helper::{
+    crate_source_files,
+    ir::{Item, Order, UseName, UseNames},
+    printer::Printer,
+    rust::RustPrinter,
+    DefaultResolver, Dialect, Resolver, Wit, WitgenAttrs,
+};
 
 // struct Empty;
 
@@ -25,6 +32,45 @@ fn parse_wit_str(s: &str) -> Result<Interface> {
 //   Interface::parse_with("a", s, |path| resolve_wit_ )
 // }
 
+/// Directory in the system's temporary directory, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("witgen-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Crate with `lib` as its `src/lib.rs`
+fn temp_crate(name: &str, lib: &str) -> Result<TempDir> {
+    let dir = TempDir::new(name)?;
+    std::fs::create_dir_all(dir.join("src"))?;
+    std::fs::write(dir.join("src/lib.rs"), lib)?;
+    Ok(dir)
+}
+
 fn parse(s: &str) {
     let res = parse_str(s).expect(s);
     parse_wit_str(&res).expect(&res);
@@ -72,3 +118,1574 @@ type Float64Bit = f64;
 "#;
     println!("{:?}", parse_wit_str(&parse_str(simple).unwrap()).unwrap())
 }
+
+#[test]
+fn component_dialect() -> Result<()> {
+    let mut witgen = Witgen::from_path(&PathBuf::from(&"examples/my_witgen_example"));
+    witgen.dialect = Some(Dialect::Component);
+    witgen.wit_package = Some("example:my-witgen-example@0.1.0".parse()?);
+    let wit = witgen.generate_str(witgen.read_input()?)?;
+    assert_matches_snapshot!(wit);
+    Ok(())
+}
+
+#[test]
+fn document_rename() -> Result<()> {
+    let wit = Wit::from_str(
+        r#"
+#[witgen]
+struct TestStruct {
+    inner: String,
+}
+
+#[witgen]
+fn test(s: TestStruct) -> Option<TestStruct> {
+    Some(s)
+}
+"#,
+    )?;
+    let (mut doc, _) = wit.to_document();
+    doc.rename("test-struct", "renamed");
+    let res = Printer::new(Dialect::Legacy).print_document(&doc)?;
+    assert_eq!(
+        res,
+        "record renamed {\n  inner: string\n}\n\ntest: func(s: renamed) -> option<renamed>\n"
+    );
+    parse_wit_str(&res)?;
+    Ok(())
+}
+
+#[test]
+fn diagnostics() -> Result<()> {
+    let wit = Wit::from_str(
+        r#"
+#[witgen]
+struct Good {
+    inner: String,
+}
+
+#[witgen]
+struct Bad {
+    inner: String,
+    callback: fn(u8),
+}
+"#,
+    )?;
+    let (doc, diagnostics) = wit.to_document();
+    assert_eq!(doc.type_names(), vec!["good"]);
+    let diagnostic = diagnostics.iter().next().unwrap();
+    assert_eq!(diagnostics.iter().count(), 1);
+    assert_eq!(diagnostic.item, "Bad");
+    assert_eq!((diagnostic.line, diagnostic.column), (10, 15));
+    assert!(diagnostic.message.starts_with("cannot serialize this type"));
+    Ok(())
+}
+
+#[test]
+fn compile_time_check() -> Result<()> {
+    let item = syn::parse_str(
+        r#"
+struct Bad {
+    inner: String,
+    callback: fn(u8),
+}
+"#,
+    )?;
+    let err = Wit::check_item(Default::default(), item).unwrap_err();
+    let start = err.span().start();
+    assert_eq!((start.line, start.column), (4, 14));
+    assert!(err.to_string().starts_with("cannot serialize this type"));
+
+    let item = syn::parse_str("fn good(s: String) -> Option<u8> { None }")?;
+    assert!(Wit::check_item(Default::default(), item).is_ok());
+
+    // Reported by the `#[witgen]` of the struct, not again by the module's
+    let item = syn::parse_str(
+        r#"
+mod api {
+    #[witgen]
+    struct Bad {
+        callback: fn(u8),
+    }
+}
+"#,
+    )?;
+    assert!(Wit::check_item(Default::default(), item).is_ok());
+    Ok(())
+}
+
+#[test]
+fn rename() -> Result<()> {
+    let source = r#"
+#[witgen(rename = "kind")]
+enum Type {
+    #[witgen(rename = "static-kind")]
+    Static,
+    Dynamic,
+}
+
+#[witgen]
+struct Spec {
+    #[witgen(rename = "kind")]
+    r#type: Type,
+}
+
+#[witgen]
+trait Api {
+    #[witgen(rename = "run")]
+    fn use_spec(#[witgen(rename = "input")] s: Spec) -> Type;
+}
+"#;
+    let res = parse_str(source)?;
+    assert_eq!(
+        res,
+        "enum kind {\n  static-kind,\n  dynamic,\n}\n\nrecord spec {\n  kind: kind\n}\n\ninterface api {\n  run: func(input: spec) -> kind\n}\n"
+    );
+    // The parser doesn't support interfaces, check the items of `api` declared at the top level
+    let (doc, _) = Wit::from_str(source)?.to_document();
+    let items: Vec<Item> = doc
+        .items
+        .into_iter()
+        .flat_map(|item| match item {
+            Item::Interface(interface) => interface.items,
+            item => vec![item],
+        })
+        .collect();
+    let flattened = Printer::default().print_items(&items, 0)?;
+    assert!(flattened.ends_with("run: func(input: spec) -> kind\n"));
+    parse_wit_str(&flattened)?;
+    Ok(())
+}
+
+#[test]
+fn rename_invalid() {
+    let wit = Wit::from_str(
+        r#"
+#[witgen(rename = "NotKebab")]
+struct Spec {
+    kind: String,
+}
+"#,
+    )
+    .unwrap();
+    let (_, diagnostics) = wit.to_document();
+    let diagnostic = diagnostics.iter().next().unwrap();
+    assert_eq!(
+        diagnostic.message,
+        "'NotKebab' is not a valid wit identifier, use kebab-case"
+    );
+    assert!(WitgenAttrs::parse_args("unknown".parse().unwrap()).is_err());
+}
+
+#[test]
+fn keywords() -> Result<()> {
+    let wit = Wit::from_str(
+        r#"
+#[witgen]
+struct Record {
+    name: String,
+}
+
+#[witgen]
+struct Interface {
+    r#type: String,
+    from: Vec<Record>,
+    constructor: bool,
+}
+
+#[witgen]
+fn r#use(list: Interface) {}
+"#,
+    )?;
+    let (doc, _) = wit.to_document();
+    let res = Printer::new(Dialect::Legacy).print_document(&doc)?;
+    assert_eq!(
+        res,
+        "record %record {\n  name: string\n}\n\nrecord %interface {\n  %type: string,\n  %from: list<%record>,\n  constructor: bool\n}\n\n%use: func(%list: %interface)\n"
+    );
+    parse_wit_str(&res)?;
+    let res = Printer::new(Dialect::Component).print_item(&doc.items[1], 0)?;
+    assert_eq!(
+        res,
+        "record %interface {\n  %type: string,\n  %from: list<%record>,\n  %constructor: bool\n}\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn legacy_keywords() -> Result<()> {
+    // `_` isn't an identifier even escaped, kebab-casing never produces it
+    for keyword in Dialect::Legacy.keywords().iter().filter(|kw| **kw != "_") {
+        let ident = Dialect::Legacy.escape(keyword);
+        parse_wit_str(&format!(
+            "record r {{\n  {ident}: u32\n}}\n\nf: func({ident}: u32)\n"
+        ))
+        .map_err(|err| err.context(format!("`{keyword}` isn't escaped")))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn flags() -> Result<()> {
+    let res = parse_str(
+        r#"
+bitflags! {
+    /// Permissions of a file
+    #[witgen]
+    pub struct Permissions: u8 {
+        /// Can be read
+        const READ = 0b01;
+        const WRITE_ONCE = 0b10;
+    }
+
+    pub struct Ignored: u8 {
+        const A = 1;
+    }
+}
+
+#[witgen]
+bitflags::bitflags! {
+    struct Both: u32 {
+        const A = 1;
+        const B = Self::A.bits << 1;
+    }
+}
+
+#[witgen(flags)]
+struct Features {
+    /// Doc of a member
+    fast: bool,
+    #[witgen(rename = "safe")]
+    secure: bool,
+}
+
+#[witgen]
+fn check(permissions: Permissions, features: Features) -> Both {
+    todo!()
+}
+"#,
+    )?;
+    assert_eq!(
+        res,
+        "/// Permissions of a file\nflags permissions {\n  /// Can be read\n  read,\n  write-once,\n}\n\nflags both {\n  a,\n  b,\n}\n\nflags features {\n  /// Doc of a member\n  fast,\n  safe,\n}\n\ncheck: func(permissions: permissions, features: features) -> both\n"
+    );
+    parse_wit_str(&res)?;
+
+    let item = syn::parse_str("struct Features { fast: bool, level: u8 }")?;
+    let err = Wit::check_item("flags".parse().unwrap(), item).unwrap_err();
+    assert_eq!(err.to_string(), "flags fields must be `bool`");
+    Ok(())
+}
+
+#[test]
+fn smart_pointers() -> Result<()> {
+    let res = parse_str(
+        r#"
+#[witgen]
+struct Inner {
+    id: u32,
+}
+
+#[witgen]
+struct Shared<'a> {
+    label: &'a str,
+    name: Arc<str>,
+    boxed: Box<str>,
+    items: Rc<Vec<u32>>,
+    bytes: Box<[u8]>,
+    inner: Box<Option<Inner>>,
+}
+
+#[witgen]
+fn borrowed<'a>(s: &'a str, bytes: &[u8], cow: Cow<'a, str>, shared: &mut Shared) -> Box<Cow<'static, [String]>> {
+    todo!()
+}
+"#,
+    )?;
+    assert_eq!(
+        res,
+        "record inner {\n  id: u32\n}\n\nrecord shared {\n  label: string,\n  name: string,\n  boxed: string,\n  items: list<u32>,\n  bytes: list<u8>,\n  inner: option<inner>\n}\n\nborrowed: func(s: string, bytes: list<u8>, cow: string, shared: shared) -> list<string>\n"
+    );
+    parse_wit_str(&res)?;
+    Ok(())
+}
+
+#[test]
+fn generics() -> Result<()> {
+    let res = parse_str(
+        r#"
+#[witgen]
+struct Page<T> {
+    items: Vec<T>,
+    total: u32,
+}
+
+#[witgen]
+enum Either<A, B> {
+    Left(A),
+    Right { value: B },
+}
+
+#[witgen]
+struct User {
+    name: String,
+}
+
+#[witgen(name = "users")]
+type Users = Page<User>;
+
+#[witgen]
+fn pages(bytes: Page<Vec<u8>>, either: Either<u8, String>) -> Page<User> {
+    let empty: Page<u64> = todo!();
+    todo!()
+}
+"#,
+    )?;
+    assert!(res.contains("record page-list-u8 {\n  items: list<list<u8>>,\n  total: u32\n}"));
+    assert!(res.contains("record users {\n  items: list<user>,\n  total: u32\n}"));
+    assert!(res
+        .contains("variant either-u8-string {\n  left(u8),\n  right(either-u8-string-right),\n}"));
+    assert!(res.contains("record either-u8-string-right {\n  value: string\n}"));
+    assert!(res.contains("pages: func(bytes: page-list-u8, either: either-u8-string) -> users\n"));
+    assert!(!res.contains("record page {"));
+    assert!(!res.contains("page-u64"), "instantiated in a function body");
+    parse_wit_str(&res)?;
+
+    let item = syn::parse_str("struct Buffer<const N: usize> { len: u32 }")?;
+    let err = Wit::check_item(Default::default(), item).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "doesn't support const generic parameters with witgen"
+    );
+    Ok(())
+}
+
+#[test]
+fn imports() -> Result<()> {
+    let res = parse_str(
+        r#"
+#[witgen]
+use dep::{Item, module::Other as Renamed, module::Same as Same};
+
+#[witgen]
+use {other::prelude::*, other::Thing as OtherThing};
+"#,
+    )?;
+    assert_eq!(
+        res,
+        "use {item, other as renamed, same} from dep\n\nuse * from other\n\nuse {thing as other-thing} from other\n"
+    );
+
+    let item = syn::parse_str("use crate::module::Item;")?;
+    let err = Wit::check_item(Default::default(), item).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("can only import from other crates"));
+    Ok(())
+}
+
+#[test]
+fn document_import() -> Result<()> {
+    let (doc, _) = Wit::from_str(
+        r#"
+#[witgen]
+struct Point {
+    x: Unit,
+}
+
+#[witgen]
+type Unit = u32;
+
+#[witgen]
+enum Unused {
+    A,
+}
+"#,
+    )?
+    .to_document();
+    let import = |names: Vec<(&str, Option<&str>)>| {
+        let names = names
+            .into_iter()
+            .map(|(name, rename)| UseName {
+                name: name.to_string(),
+                rename: rename.map(ToString::to_string),
+            })
+            .collect();
+        let imported = doc.import(&[UseNames::Names(names)]);
+        Printer::default().print_document(&imported).unwrap()
+    };
+    assert_eq!(
+        import(vec![("point", Some("coords"))]),
+        "record coords {\n  x: %unit\n}\n\ntype %unit = u32\n"
+    );
+    assert_eq!(
+        import(vec![("unit", None), ("unit", Some("length"))]),
+        "type %unit = u32\n\ntype length = %unit\n"
+    );
+    assert_eq!(doc.import(&[UseNames::Glob]).items, doc.items);
+    Ok(())
+}
+
+#[test]
+fn check() -> Result<()> {
+    let mut witgen = Witgen::from_path(&PathBuf::from(&"examples/my_witgen_example"));
+    witgen.skip_prologue = false;
+    let dir = TempDir::new("check")?;
+    witgen.output = Some(dir.join("index.wit"));
+    let wit = witgen.generate()?;
+    // Bumping cargo-witgen changes the prologue only
+    std::fs::write(
+        witgen.output(),
+        wit.replacen("(cargo-witgen v", "(cargo-witgen v0.0.0-", 1),
+    )?;
+    assert_eq!(witgen.diff()?, None);
+
+    std::fs::write(witgen.output(), wit.replace("red,\n", ""))?;
+    let diff = witgen.diff()?.unwrap();
+    assert!(diff.contains("\n+  red,\n"));
+    assert!(witgen.check().is_err());
+
+    witgen.stdout = true;
+    let err = witgen.check().unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("`--watch` and `--stdout` can only be used"));
+    Ok(())
+}
+
+#[test]
+fn source_files() -> Result<()> {
+    let root = TempDir::new("sources")?;
+    std::fs::create_dir_all(root.join("nested/inner"))?;
+    std::fs::write(
+        root.join("lib.rs"),
+        "mod nested;\nmod inline { mod missing; }\n#[path = \"other_name.rs\"]\nmod renamed;",
+    )?;
+    std::fs::write(root.join("nested.rs"), "mod inner;")?;
+    std::fs::write(root.join("nested/inner/mod.rs"), "struct Inner;")?;
+    std::fs::write(root.join("other_name.rs"), "")?;
+
+    let files = crate_source_files(&root.join("lib.rs"));
+    let files: Vec<_> = files
+        .iter()
+        .map(|path| path.strip_prefix(&root).unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            "lib.rs",
+            "nested.rs",
+            "nested/inner/mod.rs",
+            "inline/missing.rs",
+            "inline/missing/mod.rs",
+            "other_name.rs",
+        ]
+    );
+
+    let witgen = Witgen::from_path(&PathBuf::from(&"examples/my_witgen_example"));
+    assert_eq!(
+        witgen.watched_files(),
+        vec![
+            PathBuf::from("examples/my_witgen_example/src/lib.rs"),
+            PathBuf::from("examples/my_witgen_example/src/extra_type.rs"),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn workspace_members() -> Result<()> {
+    let mut witgen = Witgen::from_path(&PathBuf::from("."));
+    witgen.cargo.workspace.workspace = true;
+    let members = witgen.workspace_members()?;
+    let dirs: Vec<_> = members
+        .iter()
+        .map(|member| member.input_dir.file_name().unwrap().to_str().unwrap())
+        .collect();
+    // Path dependencies are members as well
+    assert_eq!(dirs, vec!["example_dep", "my_witgen_example"]);
+    assert!(members[1]
+        .output()
+        .ends_with("examples/my_witgen_example/index.wit"));
+
+    witgen.cargo.workspace.exclude = vec!["my_witgen_example".to_string()];
+    assert_eq!(witgen.workspace_members()?.len(), 1);
+
+    witgen.cargo.workspace.package = vec!["unknown".to_string()];
+    assert!(witgen.workspace_members().is_err());
+
+    witgen.cargo.workspace.package = vec![];
+    witgen.output = Some(PathBuf::from("missing/index.wit"));
+    let err = witgen.check().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "1 workspace members are out of date, run `cargo witgen generate` to update them"
+    );
+
+    witgen.output = Some(std::env::temp_dir().join("index.wit"));
+    let err = witgen.workspace_members().unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("is absolute, it has to be relative to the members"));
+    Ok(())
+}
+
+#[test]
+fn config() -> Result<()> {
+    let dir = temp_crate(
+        "config",
+        r#"
+#[witgen]
+struct UserDto {
+    id: Uuid,
+    friends: Vec<Uuid>,
+}
+
+#[witgen]
+fn get(id: Uuid) -> UserDto {
+    todo!()
+}
+
+/// Named like the overridden type
+#[witgen]
+fn uuid() -> Uuid {
+    todo!()
+}
+"#,
+    )?;
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        r#"
+[package]
+name = "shop"
+version = "0.1.0"
+
+[package.metadata.witgen]
+dialect = "component"
+package = "acme:shop"
+version = "1.2.0"
+skip-prologue = true
+
+[package.metadata.witgen.types]
+Uuid = "string"
+
+[package.metadata.witgen.naming]
+type-prefix = "shop-"
+strip-suffix = ["Dto"]
+"#,
+    )?;
+
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.load_config()?;
+    assert_eq!(
+        witgen.generate()?,
+        "package acme:shop@1.2.0;\n\ninterface shop {\n  record shop-user {\n    id: string,\n    friends: list<string>\n  }\n\n  get: func(id: string) -> shop-user;\n\n  /// Named like the overridden type\n  uuid: func() -> string;\n}\n"
+    );
+
+    // Command line flags take precedence
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.dialect = Some(Dialect::Legacy);
+    witgen.load_config()?;
+    assert!(witgen.generate()?.starts_with("record shop-user {\n"));
+
+    // witgen.toml takes precedence over Cargo.toml
+    std::fs::write(dir.join("witgen.toml"), "output = \"wit/shop.wit\"")?;
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.load_config()?;
+    assert_eq!(witgen.output(), dir.join("wit/shop.wit"));
+    assert_eq!(witgen.dialect(), Dialect::Legacy);
+
+    std::fs::write(dir.join("witgen.toml"), "outputs = \"shop.wit\"")?;
+    let err = Witgen::from_path(&dir).load_config().unwrap_err();
+    assert!(format!("{err:#}").contains("unknown field `outputs`"));
+    Ok(())
+}
+
+#[test]
+fn cfg() -> Result<()> {
+    let dir = temp_crate(
+        "cfg",
+        r#"
+#[cfg(feature = "extra")]
+mod extra;
+
+#[witgen]
+struct Config {
+    #[cfg(feature = "std")]
+    path: String,
+    #[cfg(not(feature = "std"))]
+    raw: Vec<u8>,
+    #[cfg_attr(target_arch = "wasm32", witgen(rename = "wasm-only"))]
+    size: u64,
+}
+
+#[witgen]
+#[cfg(target_os = "windows")]
+fn registry() -> String {
+    todo!()
+}
+"#,
+    )?;
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        r#"
+[package]
+name = "gated"
+version = "0.1.0"
+
+[features]
+default = ["std"]
+std = []
+full = ["extra", "dep:serde", "serde/derive"]
+extra = []
+"#,
+    )?;
+    std::fs::write(
+        dir.join("src/extra.rs"),
+        "#[witgen]\nfn extra(config: Config) {}\n",
+    )?;
+
+    let witgen = Witgen::from_path(&dir);
+    let wit = witgen.generate()?;
+    assert!(wit.contains("path: string"));
+    assert!(!wit.contains("raw"));
+    assert!(!wit.contains("extra"));
+    assert!(wit.contains("size: u64"));
+    assert!(!wit.contains("registry"));
+
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.cargo.features.features = vec!["full".to_string()];
+    witgen.cargo.features.no_default_features = true;
+    witgen.target = Some("wasm32-unknown-unknown".to_string());
+    let wit = witgen.generate()?;
+    assert!(wit.contains("raw: list<u8>"));
+    assert!(!wit.contains("path"));
+    assert!(wit.contains("extra: func(config: config)"));
+    assert!(wit.contains("wasm-only: u64"));
+
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.cargo.features.all_features = true;
+    witgen.target = Some("x86_64-pc-windows-msvc".to_string());
+    let wit = witgen.generate()?;
+    assert!(wit.contains("extra: func"));
+    assert!(wit.contains("registry: func() -> string"));
+    Ok(())
+}
+
+#[test]
+fn rust_round_trip() -> Result<()> {
+    let source = r#"
+/// A user
+#[witgen]
+struct User {
+    /// Unique
+    id: u64,
+    r#type: Option<Kind>,
+    scores: Vec<(String, f32)>,
+}
+
+#[witgen(rename = "kind")]
+enum UserKind {
+    Admin,
+    /// Reads only
+    Guest,
+}
+
+#[witgen]
+enum Event {
+    Created(User),
+    Moved(u32, u32),
+    Renamed { from: String, to: String },
+    Closed,
+}
+
+#[witgen(flags)]
+struct Access {
+    read: bool,
+    write_once: bool,
+}
+
+#[witgen]
+type Pair = (i8, Result<u8, String>);
+
+#[witgen]
+fn http2_get(user: User, r#match: bool) -> Result<Vec<u8>> {
+    todo!()
+}
+
+#[witgen(rename = "result")]
+struct Outcome {
+    ok: bool,
+}
+
+#[witgen]
+fn reset(this: Outcome) {}
+
+struct Counter;
+
+/// Counts things
+#[witgen]
+impl Counter {
+    pub fn new(start: u32) -> Self {
+        todo!()
+    }
+
+    pub fn get(&self) -> u32 {
+        todo!()
+    }
+
+    pub fn merge(&mut self, other: &Counter) {}
+
+    pub fn zero() -> Counter {
+        todo!()
+    }
+}
+
+#[witgen]
+trait Store {
+    /// Saved user
+    fn save(&mut self, user: User) -> u64;
+    fn count(&self) -> u32;
+}
+"#;
+    let (doc, diagnostics) = Wit::from_str(source)?.to_document();
+    assert!(diagnostics.is_empty());
+    let rust = RustPrinter.print_document(&doc)?;
+    assert!(rust.starts_with("use witgen::witgen;\n\n/// A user\n#[witgen]\npub struct User {\n"));
+    assert!(rust.contains("    pub r#type: Option<Kind>,\n"));
+    assert!(rust.contains("#[witgen(rename = \"result\")]\npub struct ResultType {\n"));
+    assert!(rust.contains("pub fn reset(this: ResultType) {\n"));
+    assert!(rust.contains("pub struct Counter;\n\n/// Counts things\n#[witgen]\nimpl Counter {\n"));
+    assert!(rust.contains("    pub fn merge(&mut self, other: &Self) {\n        todo!()\n    }\n"));
+    assert!(rust.contains("#[witgen]\npub trait Store {\n    /// Saved user\n    fn save(&mut self, user: User) -> u64;\n"));
+
+    let (round_trip, diagnostics) = Wit::from_str(&rust)?.to_document();
+    assert!(diagnostics.is_empty());
+    for dialect in [Dialect::Legacy, Dialect::Component] {
+        let printer = Printer::new(dialect);
+        assert_eq!(
+            printer.print_items(&round_trip.items, 0)?,
+            printer.print_items(&doc.items, 0)?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn diagnose_output() -> Result<()> {
+    let wit = Wit::from_str(
+        r#"
+#[witgen]
+fn ping() {}
+
+mod shapes {
+    #[witgen]
+    enum Shape {
+        Circle(f32),
+        Square { side: f32 },
+    }
+}
+"#,
+    )?;
+    let output = wit.to_wit_string(Dialect::Legacy);
+    assert_eq!(output.lines().nth(7), Some("record shape-square {"));
+
+    let err = anyhow::anyhow!("expected `:`, found `}}`\n     --> index:8:1");
+    let diagnostic = wit.diagnose_output(&output, &err).unwrap();
+    assert_eq!(diagnostic.item, "Shape");
+    assert_eq!(diagnostic.module_path, vec!["shapes".to_string()]);
+    assert_eq!((diagnostic.line, diagnostic.column), (7, 10));
+
+    let err = anyhow::anyhow!("unknown type\n --> index:1:4");
+    assert_eq!(wit.diagnose_output(&output, &err).unwrap().item, "ping");
+    // Errors without a location aren't mapped
+    assert!(wit
+        .diagnose_output(&output, &anyhow::anyhow!("no location"))
+        .is_none());
+    Ok(())
+}
+
+#[test]
+fn validate_output() -> Result<()> {
+    let dir = temp_crate(
+        "validate",
+        r#"
+#[witgen]
+struct Node {
+    next: Option<Node>,
+}
+"#,
+    )?;
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.skip_resolve = true;
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.starts_with("generated wit is invalid:\n  "));
+    assert!(err.contains("src/lib.rs:3:8: `Node`: type can recursively refer to itself"));
+
+    // Interfaces are checked one by one, with the items declared outside of them
+    std::fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+#[witgen]
+struct User {
+    name: String,
+}
+
+#[witgen]
+trait Users {
+    fn get(&self, id: u64) -> User;
+}
+"#,
+    )?;
+    let wit = witgen.generate()?;
+    assert!(wit.contains("interface users {\n  get: func(id: u64) -> user\n}\n"));
+
+    std::fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+#[witgen]
+mod users {
+    #[witgen]
+    struct Node {
+        next: Option<Node>,
+    }
+}
+"#,
+    )?;
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.contains("src/lib.rs:5:12: `Node`: type can recursively refer to itself"));
+
+    // Partial output isn't checked
+    witgen.allow_partial = true;
+    assert!(witgen
+        .generate()?
+        .contains("record node {\n    next: option<node>\n  }"));
+    Ok(())
+}
+
+#[test]
+fn unresolved_references() -> Result<()> {
+    let dir = temp_crate(
+        "references",
+        r#"
+mod model {
+    pub struct User {
+        pub name: String,
+    }
+}
+
+#[witgen]
+fn register(user: User, extra: Header) -> Option<Missing> {
+    let cached: Option<Cached> = None;
+    todo!()
+}
+"#,
+    )?;
+
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.prefix_string = vec!["record header {\n  key: string\n}\n".to_string()];
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.contains("src/lib.rs:9:19: `register`: `user` isn't declared in wit\n    help: "));
+    assert!(err.contains("src/lib.rs:3:16: `User`: add `#[witgen]` to generate it"));
+    assert!(err.contains("`register`: `missing` isn't declared in wit, annotate it with `#[witgen]` or import it with `use`"));
+    assert!(!err.contains("`header`"));
+    assert!(!err.contains("`cached`"), "declared in a function body");
+
+    // A glob can import the types the crate doesn't declare
+    witgen
+        .prefix_string
+        .push("use { token as header-token } from auth\nuse * from dep\n".to_string());
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.contains("`register`: `user` isn't declared in wit"));
+    assert!(!err.contains("`missing`"));
+    witgen.prefix_string.pop();
+
+    // Partial output can't be validated since it references the items left out
+    witgen.allow_partial = true;
+    witgen.output = Some(dir.join("index.wit"));
+    witgen.run()?;
+    let wit = std::fs::read_to_string(dir.join("index.wit"))?;
+    assert!(wit.contains("register: func(user: user, extra: header) -> option<missing>"));
+
+    std::fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+mod api {
+    #[witgen]
+    use dep::*;
+
+    #[witgen]
+    fn get() -> Imported {
+        todo!()
+    }
+}
+
+#[witgen]
+fn other() -> Imported {
+    todo!()
+}
+"#,
+    )?;
+    witgen.skip_resolve = true;
+    witgen.allow_partial = false;
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.contains("`other`: `imported` isn't declared in wit"));
+    assert!(!err.contains("`get`"), "imported by the glob of its module");
+    Ok(())
+}
+
+#[test]
+fn deep() -> Result<()> {
+    let dir = temp_crate(
+        "deep",
+        r#"
+mod model {
+    use crate::roles::Role;
+
+    pub struct User {
+        pub role: Role,
+        pub tags: Page<Tag>,
+    }
+
+    pub struct Page<T> {
+        pub items: Vec<T>,
+    }
+
+    pub type Tag = String;
+
+    pub struct Unused {
+        pub a: u8,
+    }
+}
+
+mod roles {
+    pub enum Role {
+        Admin,
+        Guest,
+    }
+}
+
+mod legacy {
+    pub enum Role {
+        Root,
+    }
+}
+
+/// Not the parameter of `Page`
+struct T {
+    a: u8,
+}
+
+#[witgen(deep)]
+fn get(id: u64) -> User {
+    let cache: Option<Unused> = None;
+    todo!()
+}
+
+#[witgen]
+fn callback(cb: Callback) {}
+
+struct Callback {
+    f: fn(u8),
+}
+"#,
+    )?;
+
+    let mut witgen = Witgen::from_path(&dir);
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.contains("`callback`: `callback` isn't declared in wit"));
+    assert!(!err.contains("`user`"));
+
+    witgen.deep = true;
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.contains("`Callback`: cannot serialize this type 'fn (u8)' to wit"));
+
+    witgen.allow_partial = true;
+    witgen.output = Some(dir.join("index.wit"));
+    witgen.run()?;
+    let wit = std::fs::read_to_string(dir.join("index.wit"))?;
+    assert_eq!(
+        wit,
+        "record user {\n  role: role,\n  tags: page-tag\n}\n\ntype tag = string\n\nenum role {\n  admin,\n  guest,\n}\n\nget: func(id: u64) -> user\n\ncallback: func(cb: callback)\n\nrecord page-tag {\n  items: list<tag>\n}\n"
+    );
+
+    // Out of scope, a name declared by several modules is ambiguous
+    std::fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+mod roles {
+    pub enum Role {
+        Admin,
+    }
+}
+
+mod legacy {
+    pub enum Role {
+        Root,
+    }
+}
+
+#[witgen(deep)]
+fn role() -> Role {
+    todo!()
+}
+"#,
+    )?;
+    witgen.allow_partial = false;
+    let err = witgen.generate().unwrap_err().to_string();
+    assert!(err.contains("src/lib.rs:15:14: `role`: `Role` is ambiguous, it could refer to `crate::roles::Role` or `crate::legacy::Role`"));
+    Ok(())
+}
+
+#[test]
+fn order() -> Result<()> {
+    let (mut doc, _) = Wit::from_str(
+        r#"
+#[witgen]
+fn get(id: Id) -> User {
+    todo!()
+}
+
+#[witgen]
+struct User {
+    role: Role,
+    manager: Option<Manager>,
+}
+
+#[witgen]
+type Id = u64;
+
+#[witgen]
+enum Role {
+    Admin,
+    Guest,
+}
+
+#[witgen]
+struct Manager {
+    reports: Vec<User>,
+}
+"#,
+    )?
+    .to_document();
+    let printer = Printer::new(Dialect::Legacy);
+    let source = printer.print_document(&doc)?;
+    doc.sort(Order::Source);
+    assert_eq!(printer.print_document(&doc)?, source);
+
+    doc.sort(Order::Topological);
+    let names: Vec<_> = doc.items.iter().filter_map(|item| item.name()).collect();
+    // Recursive types are ordered by first use
+    assert_eq!(names, ["role", "manager", "user", "id", "get"]);
+    assert_eq!("topological".parse::<Order>()?, Order::Topological);
+    assert!("alphabetical".parse::<Order>().is_err());
+    Ok(())
+}
+
+#[test]
+fn split() -> Result<()> {
+    let dir = temp_crate(
+        "split",
+        r#"
+#[witgen]
+fn version() -> String {
+    todo!()
+}
+
+#[witgen]
+struct Error {
+    message: String,
+}
+
+mod model {
+    #[witgen]
+    struct User {
+        role: Role,
+    }
+
+    #[witgen]
+    enum Role {
+        Admin,
+        Guest,
+    }
+
+    pub mod history {
+        #[witgen]
+        struct Event {
+            user: User,
+            error: Option<Error>,
+        }
+    }
+}
+
+mod api {
+    /// Users management
+    #[witgen]
+    trait Users {
+        fn get(&self, id: u64) -> Result<User, Error>;
+    }
+}
+"#,
+    )?;
+
+    let mut witgen = Witgen::from_path(&dir);
+    witgen.output = Some(dir.join("wit/index.wit"));
+    witgen.split = Some(Split::Module);
+    let files = witgen.generate_files()?;
+    let names: Vec<_> = files
+        .iter()
+        .map(|(path, _)| path.strip_prefix(&dir))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            Ok(Path::new("wit/index.wit")),
+            Ok(Path::new("wit/model.wit")),
+            Ok(Path::new("wit/model-history.wit")),
+            Ok(Path::new("wit/api.wit")),
+        ]
+    );
+    assert_eq!(
+        files[2].1,
+        "use {user} from model\n\nuse {error} from index\n\nrecord event {\n  user: user,\n  error: option<error>\n}\n"
+    );
+    assert!(files[3].1.starts_with("use {user} from model\n\nuse {error} from index\n\n/// Users management\ninterface users {\n"));
+
+    std::fs::create_dir_all(dir.join("wit"))?;
+    witgen.write_files(&files)?;
+    assert_eq!(witgen.diff()?, None);
+
+    // Every split file is validated
+    let source = std::fs::read_to_string(dir.join("src/lib.rs"))?;
+    std::fs::write(
+        dir.join("src/lib.rs"),
+        source.replace(
+            "        role: Role,",
+            "        role: Role,\n        manager: Option<User>,",
+        ),
+    )?;
+    let err = witgen.generate_files().unwrap_err().to_string();
+    assert!(err.contains("`User`: type can recursively refer to itself"));
+    std::fs::write(dir.join("src/lib.rs"), source)?;
+
+    witgen.split = Some(Split::Trait);
+    witgen.dialect = Some(Dialect::Component);
+    witgen.wit_package = Some("acme:users".parse()?);
+    let err = witgen.generate_files().unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("trait `users` can't be split out"));
+
+    witgen.wit_package = Some("acme:shop".parse()?);
+    let files = witgen.generate_files()?;
+    assert_eq!(files.len(), 2);
+    assert!(files[0]
+        .1
+        .starts_with("package acme:shop;\n\ninterface shop {\n  version: func() -> string;\n"));
+    assert_eq!(
+        files[1],
+        (
+            dir.join("wit/users.wit"),
+            "package acme:shop;\n\n/// Users management\ninterface users {\n  use shop.{user, error};\n\n  get: func(id: u64) -> result<user, error>;\n}\n".to_string()
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn module_interfaces() -> Result<()> {
+    let wit = Wit::from_str(
+        r#"
+/// Persistent storage
+#[witgen]
+mod storage {
+    //! Backed by the disk
+    #[witgen]
+    struct Config {
+        path: String,
+    }
+
+    #[witgen]
+    fn open(config: Config) -> Handle {
+        todo!()
+    }
+}
+
+#[witgen(rename = "net")]
+mod network {
+    #[witgen]
+    struct Config {
+        port: u16,
+    }
+
+    #[witgen]
+    fn connect(config: Config, handle: Handle) {}
+}
+
+#[witgen]
+type Handle = u32;
+"#,
+    )?;
+    let (mut doc, diagnostics) = wit.to_document();
+    assert!(diagnostics.is_empty());
+    doc.package = Some("acme:server".parse()?);
+    let component = Printer::new(Dialect::Component).print_document(&doc)?;
+    assert_eq!(
+        component,
+        r#"package acme:server;
+
+interface server {
+  type handle = u32;
+}
+
+/// Persistent storage
+/// Backed by the disk
+interface storage {
+  use server.{handle};
+
+  record config {
+    path: string
+  }
+
+  open: func(config: config) -> handle;
+}
+
+interface net {
+  use server.{handle};
+
+  record config {
+    port: u16
+  }
+
+  connect: func(config: config, handle: handle);
+}
+"#
+    );
+    let legacy = Printer::new(Dialect::Legacy).print_document(&doc)?;
+    assert!(legacy.starts_with(
+        "/// Persistent storage\n/// Backed by the disk\ninterface storage {\n  record config {\n"
+    ));
+
+    // References to a type declared by several interfaces are ambiguous
+    let wit = Wit::from_str(
+        r#"
+#[witgen]
+mod a {
+    #[witgen]
+    type Config = u8;
+}
+
+#[witgen]
+mod b {
+    #[witgen]
+    type Config = u16;
+}
+
+#[witgen]
+mod c {
+    #[witgen]
+    fn get() -> Config {
+        todo!()
+    }
+}
+"#,
+    )?;
+    let (mut doc, _) = wit.to_document();
+    doc.package = Some("acme:server".parse()?);
+    let err = Printer::new(Dialect::Component)
+        .print_document(&doc)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`config` used by interface `c` is declared by several interfaces: a, b"
+    );
+    Ok(())
+}
+
+#[test]
+fn worlds() -> Result<()> {
+    let wit = Wit::from_str(
+        r#"
+#[witgen]
+struct Request {
+    url: String,
+}
+
+/// Handle a request
+#[witgen(export)]
+fn handle(request: Request) -> u16 {
+    todo!()
+}
+
+#[witgen(import)]
+fn log(message: String) {}
+
+#[witgen(import)]
+trait Clock {
+    fn now() -> u64;
+}
+
+#[witgen(world = "cli")]
+mod args {
+    #[witgen]
+    fn parse(args: Vec<String>) -> Request {
+        todo!()
+    }
+}
+
+#[witgen]
+mod storage {
+    #[witgen]
+    struct Entry {
+        key: String,
+    }
+
+    #[witgen(world = "cli", import)]
+    fn read(key: String) -> Entry {
+        todo!()
+    }
+    #[witgen]
+    fn write(entry: Entry) {}
+}
+"#,
+    )?;
+    let (mut doc, diagnostics) = wit.to_document();
+    assert!(diagnostics.is_empty());
+    doc.package = Some("acme:server".parse()?);
+    let component = Printer::new(Dialect::Component).print_document(&doc)?;
+    assert_eq!(
+        component,
+        r#"package acme:server;
+
+interface server {
+  record request {
+    url: string
+  }
+}
+
+interface clock {
+  now: func() -> u64;
+}
+
+interface args {
+  use server.{request};
+
+  parse: func(args: list<string>) -> request;
+}
+
+interface storage {
+  record entry {
+    key: string
+  }
+
+  write: func(entry: entry);
+}
+
+world server-world {
+  use server.{request};
+
+  import log: func(message: string);
+  import clock;
+  /// Handle a request
+  export handle: func(request: request) -> u16;
+}
+
+world cli {
+  use storage.{entry};
+
+  import read: func(key: string) -> entry;
+  export args;
+}
+"#
+    );
+    let legacy = Printer::new(Dialect::Legacy).print_document(&doc)?;
+    assert!(legacy.contains("handle: func(request: request) -> u16\n"));
+
+    let (_, diagnostics) = Wit::from_str(
+        r#"
+#[witgen(export)]
+struct Response {
+    status: u16,
+}
+
+#[witgen(import, export)]
+fn both() {}
+"#,
+    )?
+    .to_document();
+    assert_eq!(
+        diagnostics.to_string(),
+        r#"2 item(s) couldn't be converted to wit:
+  :3:8: `Response`: only functions, traits, impls and modules can be imported or exported
+  :8:4: `both`: an item can't be both imported and exported
+"#
+    );
+    Ok(())
+}
+
+#[test]
+fn resource_handles() -> Result<()> {
+    let source = r#"
+#[witgen]
+struct Config {
+    limit: u32,
+}
+
+struct Counter;
+
+#[witgen]
+impl Counter {
+    #[witgen(constructor)]
+    pub fn with_config(config: &Config) -> Self {
+        todo!()
+    }
+
+    pub fn add(&mut self, other: Self) {}
+
+    /// Last count
+    pub fn finish(self) -> u32 {
+        todo!()
+    }
+}
+
+struct Registry;
+
+#[witgen]
+impl Registry {
+    pub fn register(&mut self, counter: Counter) {}
+
+    pub fn contains(&self, counter: &Counter) -> bool {
+        todo!()
+    }
+}
+
+#[witgen]
+fn report(counter: &Counter, config: &Config) -> Counter {
+    todo!()
+}
+"#;
+    let (doc, diagnostics) = Wit::from_str(source)?.to_document();
+    assert!(diagnostics.is_empty());
+    assert_eq!(
+        Printer::new(Dialect::Component).print_items(&doc.items, 0)?,
+        r#"record config {
+  limit: u32
+}
+
+resource counter {
+  constructor(config: config);
+  add: func(other: own<counter>);
+  /// Last count
+  finish: static func(self: own<counter>) -> u32;
+}
+
+resource registry {
+  register: func(counter: own<counter>);
+  contains: func(counter: borrow<counter>) -> bool;
+}
+
+report: func(counter: borrow<counter>, config: config) -> counter;
+"#
+    );
+    let legacy = Printer::new(Dialect::Legacy).print_items(&doc.items, 0)?;
+    assert!(legacy.contains("  static finish: func(self: counter) -> u32\n"));
+    assert!(legacy.contains("  ///@mutable\n  add: func(other: counter)\n"));
+    let rust = RustPrinter.print_document(&doc)?;
+    assert!(rust.contains("    pub fn finish(self) -> u32 {\n"));
+    assert!(rust.contains("pub fn report(counter: &Counter, config: Config) -> Counter {\n"));
+
+    let (_, diagnostics) = Wit::from_str(
+        r#"
+struct Counter;
+
+#[witgen]
+impl Counter {
+    #[witgen(constructor)]
+    pub fn reset(&mut self) -> Self {
+        todo!()
+    }
+}
+"#,
+    )?
+    .to_document();
+    assert!(diagnostics
+        .to_string()
+        .contains("`Counter`: a constructor can't take `self`"));
+
+    let (_, diagnostics) = Wit::from_str(
+        r#"
+struct Counter;
+
+#[witgen]
+impl Counter {
+    #[witgen(constructor)]
+    pub fn try_new(limit: u32) -> Option<Self> {
+        todo!()
+    }
+}
+"#,
+    )?
+    .to_document();
+    assert!(diagnostics
+        .to_string()
+        .contains("7:9: `Counter`: a constructor has to return `Self`"));
+    Ok(())
+}
+
+#[test]
+fn collisions() -> Result<()> {
+    let (doc, diagnostics) = Wit::from_str(
+        r#"
+#[witgen]
+struct HttpConfig {
+    port: u16,
+}
+
+#[witgen]
+struct HTTPConfig {
+    host: String,
+}
+
+#[witgen]
+enum Foo {
+    Bar { x: u8 },
+}
+
+#[witgen]
+struct FooBar {
+    x: u8,
+}
+
+#[witgen]
+struct Headers {
+    content_type: String,
+    ContentType: String,
+}
+
+#[witgen]
+fn headers(http_config: HttpConfig) {}
+"#,
+    )?
+    .to_document();
+    assert_eq!(
+        diagnostics.to_string(),
+        r#"3 item(s) couldn't be converted to wit:
+  :8:8: `HTTPConfig`: `http-config` is already generated by `HttpConfig`
+    help: :3:8: `HttpConfig`: rename one of them with `#[witgen(rename = "...")]`
+  :18:8: `FooBar`: `foo-bar` is already generated by `Foo`
+    help: :13:6: `Foo`: rename one of them with `#[witgen(rename = "...")]`
+  :23:8: `Headers`: fields `content_type` and `ContentType` are both named `content-type` in wit
+"#
+    );
+    // Types and functions don't share names
+    let names: Vec<_> = doc.items.iter().filter_map(|item| item.name()).collect();
+    assert_eq!(names, ["http-config", "foo", "foo-bar", "headers"]);
+
+    let item = syn::parse_str("fn get(user_id: u64, userId: u64) {}")?;
+    let err = Wit::check_item(Default::default(), item).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "parameters `user_id` and `userId` are both named `user-id` in wit"
+    );
+    Ok(())
+}